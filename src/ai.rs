@@ -0,0 +1,191 @@
+use std::{sync::Mutex, time::{Duration, Instant}, collections::{HashSet, VecDeque}};
+use rand::Rng;
+
+use crate::{renderable_object::*, piece_set::PieceSet, physics::Timer, player::{Player, Action}, Block, CollisionResult, PF_WIDTH};
+
+pub const DEFAULT_WEIGHTS: [f64; 5] = [-0.51, 0.76, -0.36, -0.18, -0.1]; //height, lines, holes, bumpiness, wells
+
+// Every resting position the block can reach by moving, rotating & soft dropping
+fn placements(start: &Block, playfield: &RenderableObject) -> Vec<Block> {
+    if start.obj.check_collision(playfield) != CollisionResult::NoCollision {return Vec::new()}
+
+    let mut rotated: Vec<Block> = (0..4).map(|rotation| { //one block per rotation, moved around instead of cloned for every state
+        let mut block = start.clone();
+        block.obj.shape = start.piece.rotations[rotation].clone().into();
+        block.rotation = rotation;
+        block
+    }).collect();
+
+    let mut seen = HashSet::from([(start.obj.pos, start.rotation)]);
+    let mut todo = vec![(start.obj.pos, start.rotation)];
+    let mut resting = Vec::new();
+
+    while let Some((pos, rotation)) = todo.pop() {
+        let block = &mut rotated[rotation];
+        let mut next = Vec::with_capacity(5);
+        for (x, y) in [(-1, 0), (1, 0), (0, 1)] {
+            block.obj.pos = pos;
+            let result = block.mov(x, y, playfield);
+            if result == CollisionResult::NoCollision {next.push((block.obj.pos, rotation));}
+            else if y != 0 {resting.push((pos, rotation));}
+        }
+        for direction in [1, -1] {
+            block.obj.pos = pos;
+            let mut block = block.clone();
+            if block.rotate(direction, playfield) == CollisionResult::NoCollision {next.push((block.obj.pos, block.rotation));}
+        }
+
+        for state in next {
+            if seen.insert(state) {todo.push(state);}
+        }
+    }
+
+    resting.into_iter().map(|(pos, rotation)| {
+        let mut block = rotated[rotation].clone();
+        block.obj.pos = pos;
+        block
+    }).collect()
+}
+
+// Higher is better
+fn evaluate(block: &Block, playfield: &RenderableObject, weights: &[f64]) -> f64 {
+    let mut board = playfield.clone();
+    block.obj.imprint_to(&mut board);
+    let lines = board.check_line_fills();
+
+    let rows = board.shape.len();
+    let heights: Vec<usize> = (0..PF_WIDTH).map(|x| board.shape.iter().position(|row| is_solid(row[x])).map_or(0, |y| rows-y)).collect();
+    let holes: usize = (0..PF_WIDTH).map(|x| board.shape.iter().skip(rows-heights[x]).filter(|row| !is_solid(row[x])).count()).sum();
+    let bumpiness: usize = heights.windows(2).map(|h| h[0].abs_diff(h[1])).sum();
+    let wells: usize = (0..PF_WIDTH).map(|x| { //walls count as infinitely high
+        let left = if x > 0 {heights[x-1]} else {usize::MAX};
+        let right = heights.get(x+1).copied().unwrap_or(usize::MAX);
+        left.min(right).saturating_sub(heights[x])
+    }).sum();
+
+    [heights.iter().sum::<usize>(), lines, holes, bumpiness, wells].iter().zip(weights).map(|(&feature, weight)| feature as f64*weight).sum()
+}
+
+// Place the current or held block wherever the heuristic likes best, returns whether the player topped out
+pub fn play(player: &mut Player, weights: &[f64]) -> bool {
+    player.finish_clear();
+    let best = |start: &Block| placements(start, &player.playfield).into_iter()
+        .map(|block| (evaluate(&block, &player.playfield, weights), block))
+        .max_by(|a, b| a.0.total_cmp(&b.0));
+
+    let (current, held) = (best(&player.current_block), best(player.swapped_in_by_hold()));
+    let (hold, target) = match (current, held) {
+        (Some(current), Some(held)) if held.0 > current.0 => (true, held.1),
+        (Some(current), _) => (false, current.1),
+        (None, Some(held)) => (true, held.1),
+        (None, None) => return true,
+    };
+
+    if hold {player.act(Action::Hold);}
+    player.act(Action::Place {rotation: target.rotation, x: target.obj.pos[0], y: target.obj.pos[1]});
+    player.act(Action::HardDrop)
+}
+
+// The AI playing the first player's pieces as fast as --bot-pps allows, driven by the game loop
+pub struct Autoplay {
+    moves: Timer,
+    endless: bool, //restarts instead of topping out, for demos
+}
+impl Autoplay {
+    pub fn new(args: &crate::Args, endless: bool, now: Instant) -> Self {Self {moves: Timer::new(Duration::from_secs_f64(1./args.bot_pps), now), endless}}
+
+    pub fn next(&self) -> Instant {self.moves.next()}
+
+    pub fn delay(&mut self, by: Duration) {self.moves.delay(by);}
+
+    // Play whatever moves are due, returns whether the player topped out
+    pub fn update(&mut self, args: &crate::Args, player: &Mutex<Player>, now: Instant) -> bool {
+        while self.moves.tick(now) {
+            let player = &mut player.lock().unwrap();
+            if play(player, &args.ai_weights) || player.topped_out() {
+                if !self.endless {return true}
+                player.act(Action::Restart);
+            }
+        }
+        false
+    }
+}
+
+// Attract mode, the AI takes over a game nobody has touched yet after a while & hands a fresh one back on the next key press
+// Games someone has pressed a key in are never thrown away
+pub struct IdleDemo {
+    after: Duration,
+    since: Option<Instant>, //when the untouched game started, None once someone's playing it
+    demo: Option<Autoplay>, //while the demo's running
+}
+impl IdleDemo {
+    pub fn new(args: &crate::Args, now: Instant) -> Self {Self {after: Duration::from_secs(args.idle_demo), since: Some(now), demo: None}}
+
+    pub fn running(&self) -> bool {self.demo.is_some()}
+
+    pub fn next(&self) -> Option<Instant> {self.demo.as_ref().map(Autoplay::next).or(self.since.map(|since| since + self.after))}
+
+    pub fn delay(&mut self, by: Duration) {
+        if let Some(since) = &mut self.since {*since += by;}
+        if let Some(demo) = &mut self.demo {demo.delay(by);}
+    }
+
+    // Start the demo if it's been idle for long enough & play whatever moves are due
+    pub fn update(&mut self, args: &crate::Args, piece_set: &PieceSet, players: &[Mutex<Player>], objects: &Mutex<Vec<RenderableObject>>, now: Instant) {
+        let Some(demo) = &mut self.demo else {
+            if self.since.is_none_or(|since| now < since + self.after) {return}
+            log::info!("nobody's playing, starting the demo");
+            replace(args, piece_set, &players[0], true);
+            let y = players[0].lock().unwrap().bottom();
+            objects.lock().unwrap().push(RenderableObject::new([3, y], VecDeque::from(vec![Vec::new(), Vec::from(*b"DEMO  Any key to play, Esc to exit")]), (1,1), false));
+            self.demo = Some(Autoplay::new(args, true, now));
+            return
+        };
+
+        demo.update(args, &players[0], now); //goes on forever
+    }
+
+    // A key was pressed, returns whether that ended the demo & the player needs a new game
+    pub fn key(&mut self, args: &crate::Args, piece_set: &PieceSet, players: &[Mutex<Player>], objects: &Mutex<Vec<RenderableObject>>, now: Instant) -> bool {
+        self.since = self.demo.is_some().then_some(now); //the new game starts untouched
+        if self.demo.take().is_none() {return false}
+        log::info!("demo interrupted, starting a new game");
+        objects.lock().unwrap().pop();
+        replace(args, piece_set, &players[0], false);
+        true
+    }
+}
+
+// Start over with a new game in the same place
+fn replace(args: &crate::Args, piece_set: &PieceSet, player: &Mutex<Player>, is_bot: bool) {
+    let player = &mut player.lock().unwrap();
+    **player = Player::new(args, &player.redraw, piece_set, player.controls.clone(), player.playfield.pos[0], rand::thread_rng().gen());
+    player.is_bot = is_bot;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::sync::atomic::Ordering;
+    use crate::piece_set::BUILTIN;
+
+    // Plays hundreds of pieces per set with garbage coming in to shake out any index arithmetic going out of bounds
+    #[test]
+    fn hundreds_of_pieces() {
+        for (name, _) in BUILTIN {
+            let mut player = crate::player::test_player(&crate::Args::parse_from(["tetris-rs", "--pieces", name]), 1234);
+
+            let mut games = 0;
+            for piece in 0..500 {
+                if piece % 7 == 0 {player.incoming.fetch_add(piece % 3, Ordering::SeqCst);}
+                if play(&mut player, &DEFAULT_WEIGHTS) || player.topped_out() {
+                    player.act(Action::Restart);
+                    games += 1;
+                }
+            }
+            assert!(player.drawn >= 500, "{}", name);
+            assert!(games < 50, "{} keeps topping out", name);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::{sync::Mutex, time::{Duration, Instant}};
+
+use crate::{renderable_object::*, player::Player};
+
+const FLASH: Duration = Duration::from_millis(50);      //cleared rows switch between white & empty this often
+const LOCK_FLASH: Duration = Duration::from_millis(100); //placed pieces light up this long
+pub const GREY_OUT: Duration = Duration::from_millis(600); //game over greys the stack from top to bottom in this time
+
+// Per player, everything is timed so that it looks the same at any framerate
+pub struct Animations {
+    pub clear_delay: Duration, //cleared rows stay & flash this long before collapsing, new pieces wait for them
+    clearing: Option<(Vec<usize>, Instant)>,
+    locked: Option<(Vec<[isize; 2]>, Instant)>,
+}
+impl Animations {
+    pub fn new(clear_delay: Duration) -> Self {Self {clear_delay, clearing: None, locked: None}}
+}
+
+impl Player {
+    // Remove filled rows right away or once their animation ends
+    pub fn clear_rows(&mut self, rows: Vec<usize>) {
+        match &mut self.animations {
+            Some(animations) if !rows.is_empty() && !animations.clear_delay.is_zero() => animations.clearing = Some((rows, Instant::now())),
+            _ => self.playfield.remove_lines(&rows),
+        }
+    }
+
+    // Collapse rows that are still flashing, any input skips the rest of their animation
+    pub fn finish_clear(&mut self) {
+        if let Some((rows, _)) = self.animations.as_mut().and_then(|animations| animations.clearing.take()) {self.playfield.remove_lines(&rows);}
+    }
+
+    pub fn rows_pending(&self) -> bool {self.animations.as_ref().is_some_and(|animations| animations.clearing.is_some())}
+
+    // Whether rows are still flashing, finishes them once it's time
+    pub fn clearing(&mut self) -> bool {
+        let Some(animations) = &self.animations else {return false};
+        match &animations.clearing {
+            Some((_, since)) if since.elapsed() < animations.clear_delay => true,
+            Some(_) => {
+                self.finish_clear();
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn flash_lock(&mut self, cells: Vec<[isize; 2]>) {
+        if let Some(animations) = &mut self.animations {animations.locked = Some((cells, Instant::now()));}
+    }
+
+    pub fn animating(&self) -> bool {
+        self.animations.as_ref().is_some_and(|animations| animations.clearing.is_some() || animations.locked.as_ref().is_some_and(|(_, since)| since.elapsed() < LOCK_FLASH))
+    }
+
+    // Playfield as it should look right now, if there's something going on
+    pub fn animated_playfield(&self) -> Option<RenderableObject> {
+        let animations = self.animations.as_ref()?;
+        let clearing = animations.clearing.as_ref();
+        let locked = animations.locked.as_ref().filter(|(_, since)| since.elapsed() < LOCK_FLASH);
+        if clearing.is_none() && locked.is_none() {return None}
+
+        let mut playfield = self.playfield.clone();
+        if let Some((cells, _)) = locked {
+            for &[x, y] in cells {
+                if let Some(cell) = playfield.shape.get_mut(y as usize).and_then(|row| row.get_mut(x as usize)) {*cell = 1;}
+            }
+        }
+        if let Some((rows, since)) = clearing {
+            let lit = (since.elapsed().as_millis()/FLASH.as_millis()).is_multiple_of(2);
+            for &row in rows {playfield.shape[row].fill(lit as u8);}
+        }
+        Some(playfield)
+    }
+}
+
+// Grey out the top part of every stack, progress going from 0 to 1
+pub fn grey_out(players: &[Mutex<Player>], progress: f64) {
+    for player in players {
+        let player = &mut player.lock().unwrap();
+        player.finish_clear();
+
+        let rows = (progress*player.playfield.shape.len() as f64).ceil() as usize;
+        for row in player.playfield.shape.iter_mut().take(rows) {
+            for cell in row.iter_mut().filter(|cell| is_solid(**cell)) {*cell = 2;}
+        }
+    }
+}
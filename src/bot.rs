@@ -0,0 +1,239 @@
+use std::{thread, sync::{Arc, Mutex, mpsc::{self, Receiver, RecvTimeoutError}}, io::{self, BufRead, BufReader, Write}, process::{Child, ChildStdin, Command, Stdio}, time::Duration};
+use bombs::{Bomb, Fuse};
+use serde::{Serialize, Deserialize};
+
+use crate::{renderable_object::*, piece_set::PieceSet, player::{Player, Action}, PF_HEIGHT, PF_WIDTH};
+
+const TBP_HEIGHT: usize = 40; //bots see a taller board, the rows above ours are always empty
+const POLL: Duration = Duration::from_millis(50); //how often a thinking bot gets interrupted to check whether the game's over
+
+// Tetris Bot Protocol, one JSON object per line over the bot's stdin & stdout
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FrontendMessage {
+    Rules {},
+    Start {hold: Option<char>, queue: Vec<char>, combo: u32, back_to_back: bool, board: Vec<Vec<Option<char>>>},
+    Suggest,
+    Play {#[serde(rename = "move")] mv: Move},
+    NewPiece {piece: char},
+    Stop,
+    Quit,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BotMessage {
+    Info {},
+    Ready,
+    Error {reason: String},
+    Suggestion {moves: Vec<Move>},
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Move {
+    location: Location,
+    #[serde(default)]
+    spin: Spin,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Location {
+    #[serde(rename = "type")]
+    piece: char,
+    orientation: Orientation,
+    x: isize, //centre of the piece, from the bottom left
+    y: isize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Orientation {North, East, South, West} //same order as our rotations
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+enum Spin {#[default] None, Mini, Full}
+
+impl Location {
+    // Playfield cells covered by the piece, as [x, y] with y pointing down like everywhere else
+    fn cells(&self) -> Option<Vec<[isize; 2]>> {
+        let minos: [[isize; 2]; 4] = match self.piece { //north orientation, y pointing up
+            'I' => [[-1, 0], [0, 0], [1, 0], [2, 0]],
+            'O' => [[ 0, 0], [1, 0], [0, 1], [1, 1]],
+            'T' => [[-1, 0], [0, 0], [1, 0], [0, 1]],
+            'L' => [[-1, 0], [0, 0], [1, 0], [1, 1]],
+            'J' => [[-1, 0], [0, 0], [1, 0], [-1, 1]],
+            'S' => [[-1, 0], [0, 0], [0, 1], [1, 1]],
+            'Z' => [[-1, 1], [0, 1], [0, 0], [1, 0]],
+            _ => return None,
+        };
+        Some(minos.iter().map(|&[x, y]| {
+            let [x, y] = match self.orientation {
+                Orientation::North => [x, y], Orientation::East => [y, -x], Orientation::South => [-x, -y], Orientation::West => [-y, x],
+            };
+            [self.x + x, PF_HEIGHT-1 - (self.y + y)]
+        }).collect())
+    }
+}
+
+pub struct Bot {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<io::Result<String>>, //read by a thread of its own, so that a hanging bot can't hang the game
+}
+impl Bot {
+    pub fn spawn(cmd: &str, piece_set: &PieceSet) -> io::Result<Self> {
+        if !piece_set.pieces.iter().all(|p| matches!(p.name.as_str(), "I" | "J" | "L" | "O" | "S" | "T" | "Z")) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "bots only support piece sets made out of the standard tetrominoes (I, J, L, O, S, T & Z)"))
+        }
+
+        let mut parts = cmd.split_whitespace();
+        let mut child = Command::new(parts.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty bot command"))?)
+            .args(parts)
+            .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null())
+            .spawn()?;
+        let (sender, lines) = mpsc::channel();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        thread::spawn(move || { //ends once the bot's killed
+            for line in stdout.lines() {
+                if sender.send(line).is_err() {break}
+            }
+        });
+        let mut bot = Self {stdin: child.stdin.take().unwrap(), lines, child};
+
+    // Handshake
+        match bot.recv(None)? {
+            BotMessage::Info {..} => (),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "bot didn't introduce itself, is it speaking TBP?")),
+        }
+        bot.send(&FrontendMessage::Rules {})?;
+        match bot.recv(None)? {
+            BotMessage::Ready => {log::info!("bot '{}' is ready", cmd); Ok(bot)}
+            BotMessage::Error {reason} => Err(io::Error::new(io::ErrorKind::Unsupported, format!("bot rejected the rules: {}", reason))),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "bot didn't answer the rules")),
+        }
+    }
+
+    pub fn name(cmd: &str) -> &str {cmd.split_whitespace().next().unwrap_or(cmd)}
+
+    fn send(&mut self, msg: &FrontendMessage) -> io::Result<()> {
+        let mut buf = serde_json::to_vec(msg)?;
+        buf.push(b'\n');
+        self.stdin.write_all(&buf)?;
+        self.stdin.flush()
+    }
+
+    // Wait for the next message, gives up once the bomb goes off
+    fn recv(&mut self, bomb: Option<&Bomb<()>>) -> io::Result<BotMessage> {
+        let line = loop {
+            match self.lines.recv_timeout(POLL) {
+                Ok(line) => break line?,
+                Err(RecvTimeoutError::Timeout) if bomb.is_none_or(|bomb| bomb.exploded().is_none()) => (),
+                Err(RecvTimeoutError::Timeout) => return Err(io::Error::new(io::ErrorKind::Interrupted, "game ended while the bot was thinking")),
+                Err(RecvTimeoutError::Disconnected) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "bot exited")),
+            }
+        };
+        serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+fn piece_char(player: &Player, cell: u8) -> Option<char> {
+    match cell {
+        1 => Some('G'), //garbage
+        c if is_solid(c) => player.defs[(c-PIECE_CELL) as usize].piece.name.chars().next(),
+        _ => None,
+    }
+}
+
+fn start(player: &Player) -> FrontendMessage {
+    let board = (0..TBP_HEIGHT).map(|y| match (PF_HEIGHT as usize).checked_sub(y+1).and_then(|row| player.playfield.shape.get(row)) {
+        Some(row) => row.iter().map(|&cell| piece_char(player, cell)).collect(),
+        None => vec![None; PF_WIDTH],
+    }).collect();
+
+    FrontendMessage::Start {
+        hold: player.held_piece().and_then(|piece| piece.name.chars().next()),
+        queue: [&player.current_block].into_iter().chain(player.queue.iter()).filter_map(|block| block.piece.name.chars().next()).collect(),
+        combo: player.combo as u32,
+        back_to_back: player.back_to_back > 0,
+        board,
+    }
+}
+
+// Ask the bot for a move & play it, returns whether the bot topped out or gave up
+fn turn(bot: &mut Bot, bomb: &Bomb<()>, player: &Mutex<Player>, restart: &mut bool) -> io::Result<bool> {
+    if *restart { //the bot's idea of the board is wrong, e.g. because garbage rose
+        bot.send(&FrontendMessage::Stop)?;
+        bot.send(&start(&player.lock().unwrap()))?;
+        *restart = false;
+    }
+
+    bot.send(&FrontendMessage::Suggest)?; //thinking happens without holding the player's lock
+    let mv = loop {
+        if let BotMessage::Suggestion {moves} = bot.recv(Some(bomb))? {break moves.into_iter().next()}
+    };
+    let Some(mv) = mv else {return Ok(true)};
+
+    let player = &mut player.lock().unwrap();
+    let drawn = player.drawn;
+
+// Hold if the bot wants to play another piece
+    if !player.current_block.piece.name.starts_with(mv.location.piece) {player.act(Action::Hold);}
+
+// Place & lock
+    let Some(cells) = mv.location.cells() else {return Ok(true)};
+    if !player.current_block.piece.name.starts_with(mv.location.piece) || !player.place(mv.location.orientation as usize, &cells) {
+        return Ok(true) //illegal moves count as giving up
+    }
+
+    let mut expected = player.playfield.clone();
+    player.current_block.obj.imprint_to(&mut expected);
+    expected.check_line_fills();
+
+    if player.act(Action::HardDrop) {return Ok(true)}
+    player.finish_clear(); //no waiting for animations
+    *restart = player.playfield.shape != expected.shape;
+
+// Tell the bot about the move & the newly revealed pieces
+    if !*restart {
+        bot.send(&FrontendMessage::Play {mv})?;
+        for block in player.queue.iter().skip(player.queue.len() - (player.drawn-drawn)) {
+            if let Some(piece) = block.piece.name.chars().next() {bot.send(&FrontendMessage::NewPiece {piece})?;}
+        }
+    }
+    Ok(false)
+}
+
+pub fn thread(
+    args: Arc<crate::Args>,
+    bomb: bombs::Bomb<()>,
+    mut bot: Bot,
+    player_idx: usize,
+    players: Arc<Vec<Mutex<Player>>>,
+    fuse: Arc<Mutex<Option<Fuse<()>>>>
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let delay = Duration::from_secs_f64(1./args.bot_pps);
+        let mut restart = true;
+
+    // Main loop
+        while bomb.exploded().is_none() { //check for close signal
+            thread::sleep(delay);
+            if bomb.exploded().is_some() {break}
+
+            let result = turn(&mut bot, &bomb, &players[player_idx], &mut restart);
+            if bomb.exploded().is_some() {break} //not the bot's fault
+            if let Err(e) = &result {log::warn!("bot of player {} failed: {}", player_idx+1, e);}
+            if result.unwrap_or(true) { //a crashed bot loses too
+                crate::rendering::lost(&players, &fuse, player_idx);
+                break;
+            }
+        }
+
+    // Exit
+        _=bot.send(&FrontendMessage::Quit);
+        _=bot.child.kill(); //in case it doesn't listen
+        _=bot.child.wait();
+    })
+}
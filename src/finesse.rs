@@ -0,0 +1,164 @@
+use std::{collections::{HashSet, VecDeque}, sync::Mutex, time::Duration};
+
+use crate::{renderable_object::*, player::{Action, Player}, Block, CollisionResult};
+
+const DAS_REPEAT: Duration = Duration::from_millis(60); //moves coming in faster than this are a held key repeating, not presses
+const DAS_DELAY: Duration = Duration::from_millis(700); //terminals wait up to this long before a held key starts repeating
+
+// Per player counters, inputs are the moves & rotations pressed for the current piece
+#[derive(Default)]
+pub struct Finesse {
+    pub strict: bool, //send pieces back to the top instead of locking them on a fault
+    pub inputs: usize,
+    pub faults: usize,
+    pub pieces: usize, //placements that were checked
+    pub wasted: usize, //inputs more than needed, summed over every fault
+    held: Option<(isize, Duration, bool)>, //direction & time of the last move, to tell held keys from presses, & whether it might've been a held key's first repeat
+}
+impl Finesse {
+    pub fn new(strict: bool) -> Self {Self {strict, ..Default::default()}}
+
+    // Count a press, a move repeating the last one right away is the same key being held (DAS)
+    // The first repeat comes only after the terminal's delay, it counts as a press until the quick ones after it show that the key was held
+    pub fn input(&mut self, action: Action, now: Duration) {
+        match action {
+            Action::Left | Action::Right => {
+                let direction = if matches!(action, Action::Left) {-1} else {1};
+                let since = self.held.filter(|&(held, ..)| held == direction).map(|(_, at, first)| (now.saturating_sub(at), first));
+                let first = match since {
+                    Some((since, first)) if since <= DAS_REPEAT => {
+                        if first {self.inputs -= 1;}
+                        false
+                    }
+                    Some((since, _)) => {
+                        self.inputs += 1;
+                        since <= DAS_DELAY
+                    }
+                    None => {
+                        self.inputs += 1;
+                        false
+                    }
+                };
+                self.held = Some((direction, now, first));
+            }
+            Action::RotateLeft | Action::RotateRight => (self.inputs, self.held) = (self.inputs + 1, None),
+            _ => (),
+        }
+    }
+
+    pub fn reset(&mut self) {(self.inputs, self.held) = (0, None);}
+}
+
+// Where a block's cells end up, ignoring how far it fell
+fn footprint(block: &Block) -> Vec<[isize; 2]> {
+    let mut cells: Vec<[isize; 2]> = block.obj.cells().into_iter().map(|(cell, _)| cell).collect();
+    let top = cells.iter().map(|cell| cell[1]).min().unwrap_or(0);
+    for cell in cells.iter_mut() {cell[1] -= top;}
+    cells.sort_unstable();
+    cells
+}
+
+// Fewest presses that get a freshly spawned block above where the given one is, on an empty playfield, holding a direction all the way to the wall counts as one
+fn minimum(spawn: &Block, target: &Block, playfield: &RenderableObject) -> Option<(usize, Block)> {
+    let mut empty = playfield.clone();
+    for row in empty.shape.iter_mut() {row.fill(0);}
+    let goal = footprint(target);
+
+    let mut seen = HashSet::from([(spawn.obj.pos, spawn.rotation)]);
+    let mut todo = VecDeque::from([(spawn.clone(), 0)]);
+    while let Some((block, presses)) = todo.pop_front() {
+        if footprint(&block) == goal {return Some((presses, block))}
+
+        for step in 0..6 {
+            let mut next = block.clone();
+            let result = match step {
+                0 => next.mov(-1, 0, &empty),
+                1 => next.mov(1, 0, &empty),
+                2 => next.rotate(-1, &empty),
+                3 => next.rotate(1, &empty),
+                4 => das(&mut next, -1, &empty),
+                _ => das(&mut next, 1, &empty),
+            };
+            if result == CollisionResult::NoCollision && seen.insert((next.obj.pos, next.rotation)) {todo.push_back((next, presses+1));}
+        }
+    }
+    None
+}
+
+// Move as far as possible in one direction, collides if it didn't get anywhere
+fn das(block: &mut Block, direction: isize, playfield: &RenderableObject) -> CollisionResult {
+    let result = block.mov(direction, 0, playfield);
+    while block.mov(direction, 0, playfield) == CollisionResult::NoCollision {}
+    result
+}
+
+impl Player {
+    // Compare the inputs for the current block with the fewest possible right before it locks, returns whether strict mode sent it back up
+    pub fn check_finesse(&mut self) -> bool {
+        let Some(finesse) = &self.finesse else {return false};
+        let (inputs, strict) = (finesse.inputs, finesse.strict);
+        if self.is_bot {return false}
+
+        let spawn = &self.defs[self.current_block.id];
+        let Some((fewest, mut dropped)) = minimum(spawn, &self.current_block, &self.playfield) else {return false};
+        while dropped.mov(0, 1, &self.playfield) == CollisionResult::NoCollision {}
+        if dropped.obj.pos != self.current_block.obj.pos {return false} //tucks & spins can't be done from the top
+
+        let finesse = self.finesse.as_mut().unwrap();
+        finesse.pieces += 1;
+        finesse.reset();
+        if inputs <= fewest {return false}
+
+        finesse.faults += 1;
+        finesse.wasted += inputs - fewest;
+        if strict {self.current_block = self.defs[self.current_block.id].clone();}
+        strict
+    }
+}
+
+// End of game summary, one line per player
+pub fn summary(players: &[Mutex<Player>], pos: [isize; 2]) -> Option<RenderableObject> {
+    let rows: VecDeque<Vec<u8>> = players.iter().enumerate().filter_map(|(i, player)| {
+        let player = player.lock().unwrap();
+        let finesse = player.finesse.as_ref().filter(|_| !player.is_bot)?;
+        let clean = (finesse.pieces - finesse.faults) as f64*100./finesse.pieces.max(1) as f64;
+        Some(format!("FINESSE P{}  {} faults in {} pieces ({:.0}% clean), {} extra inputs", i+1, finesse.faults, finesse.pieces, clean, finesse.wasted).into_bytes())
+    }).collect();
+    (!rows.is_empty()).then(|| RenderableObject::new(pos, rows, (1,1), false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    // Holding left or right all the way is one press, however far the wall is
+    #[test]
+    fn das_to_the_walls() {
+        let args = crate::Args::parse_from(["tetris-rs"]);
+        let player = crate::player::test_player(&args, 1);
+        for name in ["O", "I"] {
+            let spawn = player.defs.iter().find(|block| block.piece.name == name).unwrap();
+            for direction in [-1, 1] {
+                let mut target = spawn.clone();
+                das(&mut target, direction, &player.playfield);
+                assert_eq!(minimum(spawn, &target, &player.playfield).map(|(presses, _)| presses), Some(1), "{} to the {} wall", name, direction);
+            }
+        }
+    }
+
+    fn inputs(moves: &[(Action, u64)]) -> usize {
+        let mut finesse = Finesse::default();
+        for &(action, ms) in moves {finesse.input(action, Duration::from_millis(ms));}
+        finesse.inputs
+    }
+
+    // With the terminal's usual timing, a key held down repeats once after half a second & then every 33ms
+    #[test]
+    fn held_keys() {
+        assert_eq!(inputs(&[0, 500, 533, 566, 600].map(|ms| (Action::Left, ms))), 1);
+        assert_eq!(inputs(&[0, 300, 800, 833, 866].map(|ms| (Action::Left, ms)).into_iter().chain([(Action::Right, 900)]).collect::<Vec<_>>()), 3); //a tap, then held
+        assert_eq!(inputs(&[0, 150, 300, 450, 600].map(|ms| (Action::Left, ms))), 5); //tapped, not held
+        assert_eq!(inputs(&[(Action::Left, 0), (Action::RotateRight, 200), (Action::Left, 400), (Action::Left, 433)]), 3); //rotating in between ends the hold
+    }
+}
@@ -0,0 +1,163 @@
+use std::{io::{self, Write}, sync::Arc};
+use crossterm::{queue, cursor, terminal::{self, Clear, ClearType}, style::{Color, Print, SetBackgroundColor, SetForegroundColor}};
+
+use crate::theme::Theme;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Cell {
+    ch: char,
+    fg: Color,
+    bg: Color,
+}
+const BLANK: Cell = Cell {ch: ' ', fg: Color::Reset, bg: Color::Reset};
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Charset {Unicode, Ascii}
+impl Charset {
+    // Unicode if the locale says UTF-8, Windows terminals manage either way
+    pub fn detect() -> Self {
+        if cfg!(windows) {return Self::Unicode}
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"].iter().find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty())).unwrap_or_default().to_lowercase();
+        if locale.contains("utf-8") || locale.contains("utf8") {Self::Unicode} else {Self::Ascii}
+    }
+}
+
+// Grid of character cells everything is drawn into, only the differences to the previous frame get sent to the terminal
+// (0, 0) is where the cursor was when the game started, the cursor goes back there after every frame
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    rows: Vec<Vec<Cell>>,
+    pub ascii: bool, //objects get drawn using only ASCII characters
+    pub theme: Arc<Theme>,
+}
+impl Frame {
+    pub fn new(charset: Charset, theme: &Arc<Theme>) -> Self {Self {rows: Vec::new(), ascii: charset == Charset::Ascii, theme: Arc::clone(theme)}}
+
+    pub fn put(&mut self, x: isize, y: isize, ch: char, fg: Color, bg: Color) {self.set(x, y, Cell {ch, fg, bg})}
+
+    // Colour the upper or lower half of a cell, keeping whatever is in the other half, backgrounds count as colours too
+    pub fn put_half(&mut self, x: isize, y: isize, lower: bool, colour: Color) {
+        if x < 0 || y < 0 {return}
+        let old = self.get(x as usize, y as usize);
+        let bg = Some(old.bg).filter(|&bg| bg != Color::Reset);
+        let (mut upper_half, mut lower_half) = match old.ch {
+            '▀' => (Some(old.fg), bg),
+            '▄' => (bg, Some(old.fg)),
+            '█' => (Some(old.fg), Some(old.fg)),
+            _ => (bg, bg),
+        };
+        if lower {lower_half = Some(colour)} else {upper_half = Some(colour)}
+
+        self.set(x, y, match (upper_half, lower_half) {
+            (Some(upper), Some(lower)) if upper == lower => Cell {ch: '█', fg: upper, bg: Color::Reset},
+            (Some(upper), lower) => Cell {ch: '▀', fg: upper, bg: lower.unwrap_or(Color::Reset)},
+            (None, lower) => Cell {ch: '▄', fg: lower.unwrap_or(colour), bg: Color::Reset},
+        });
+    }
+
+    fn set(&mut self, x: isize, y: isize, cell: Cell) {
+        if x < 0 || y < 0 {return}
+        let (x, y) = (x as usize, y as usize);
+
+        if self.rows.len() <= y {self.rows.resize(y+1, Vec::new());}
+        let row = &mut self.rows[y];
+        if row.len() <= x {row.resize(x+1, BLANK);}
+        row[x] = cell;
+    }
+
+    // Width & height
+    pub fn size(&self) -> (usize, usize) {(self.rows.iter().map(Vec::len).max().unwrap_or(0), self.rows.len())}
+
+    // Cut off whatever doesn't fit, anything wrapping around or scrolling away would break the cursor arithmetic
+    fn clip(&mut self, width: usize, height: usize) {
+        self.rows.truncate(height);
+        for row in self.rows.iter_mut() {row.truncate(width);}
+    }
+
+    fn get(&self, x: usize, y: usize) -> Cell {self.rows.get(y).and_then(|row| row.get(x)).copied().unwrap_or(BLANK)}
+
+    // Queue whatever changed since the old frame was drawn, left is the column (0, 0) is at, ends with the cursor back there
+    fn draw_over<W: Write>(&self, old: &Self, left: u16, buf: &mut W) -> io::Result<()> {
+        let (mut cx, mut cy) = (0, 0);
+        let (mut fg, mut bg) = (Color::Reset, Color::Reset);
+
+        for y in 0..self.rows.len().max(old.rows.len()) {
+            let width = self.rows.get(y).map_or(0, Vec::len).max(old.rows.get(y).map_or(0, Vec::len));
+            for x in 0..width {
+                let cell = self.get(x, y);
+                if cell == old.get(x, y) {continue}
+
+            // Move there, newlines instead of cursor::MoveDown so that the terminal scrolls if the game doesn't fit yet
+                if y > cy {queue!(buf, Print("\n".repeat(y-cy)))?;}
+                if y != cy || x != cx {queue!(buf, cursor::MoveToColumn(left + x as u16))?;}
+                (cx, cy) = (x, y);
+
+                if cell.fg != fg {
+                    queue!(buf, SetForegroundColor(cell.fg))?;
+                    fg = cell.fg;
+                }
+                if cell.bg != bg {
+                    queue!(buf, SetBackgroundColor(cell.bg))?;
+                    bg = cell.bg;
+                }
+                queue!(buf, Print(cell.ch))?;
+                cx += 1;
+            }
+        }
+
+        if fg != Color::Reset {queue!(buf, SetForegroundColor(Color::Reset))?;}
+        if bg != Color::Reset {queue!(buf, SetBackgroundColor(Color::Reset))?;}
+        if cy > 0 {queue!(buf, cursor::MoveUp(cy as u16))?;}
+        queue!(buf, cursor::MoveToColumn(left))
+    }
+}
+
+// Where frames end up, either inline starting at the cursor or centred on the alternate screen
+pub struct Screen {
+    fullscreen: bool,
+    shown: Option<(Frame, (u16, u16))>, //what's on the screen right now & the terminal size it was drawn for
+    size: Option<(u16, u16)>, //pretend the terminal is this big instead of asking it
+}
+impl Screen {
+    pub fn new(fullscreen: bool) -> Self {Self {fullscreen, shown: None, size: None}}
+    #[cfg(test)]
+    pub fn sized(fullscreen: bool, size: (u16, u16)) -> Self {Self {fullscreen, shown: None, size: Some(size)}}
+
+    // Whatever was on the screen is gone, the next frame gets drawn from scratch
+    pub fn forget(&mut self) {self.shown = None;}
+
+    pub fn show<W: Write>(&mut self, mut frame: Frame, buf: &mut W) -> io::Result<()> {
+        let size = self.size.or_else(|| terminal::size().ok()).unwrap_or((80, 24));
+        frame.clip(size.0 as usize, size.1 as usize);
+        let (width, height) = frame.size();
+
+    // Everything gets drawn again at first, after resizing & whenever a centred layout moves
+        let old = match self.shown.take() {
+            Some((old, old_size)) if old_size == size && (!self.fullscreen || old.size() == frame.size()) => old,
+            _ if self.fullscreen => {
+                queue!(buf, Clear(ClearType::All))?;
+                Frame::default()
+            }
+            _ => {
+                queue!(buf, cursor::MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+                if height > 1 {queue!(buf, Print("\n".repeat(height-1)), cursor::MoveUp(height as u16-1))?;} //scroll now so that nothing scrolls away later
+                Frame::default()
+            }
+        };
+
+        if frame.rows == old.rows { //nothing to do
+            self.shown = Some((frame, size));
+            return Ok(())
+        }
+
+        let left = if self.fullscreen {
+            let origin = ((size.0 as usize).saturating_sub(width) as u16/2, (size.1 as usize).saturating_sub(height) as u16/2);
+            queue!(buf, cursor::MoveTo(origin.0, origin.1))?;
+            origin.0
+        } else {0};
+        frame.draw_over(&old, left, buf)?;
+
+        self.shown = Some((frame, size));
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::{fs, env, path::PathBuf, collections::{BTreeMap, VecDeque}, sync::Mutex};
+use serde::{Serialize, Deserialize};
+
+use crate::{renderable_object::*, player::Player};
+
+const TABLE_LEN: usize = 10;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    name: String,
+    score: usize,
+    lines: usize,
+    time: u64, //seconds
+    date: String,
+}
+
+// Every table, keyed by mode & ruleset
+type Tables = BTreeMap<String, Vec<Entry>>;
+
+fn path() -> Option<PathBuf> {dirs::data_dir().map(|dir| dir.join("tetris-rs").join("highscores.json"))}
+
+fn load() -> Tables {
+    path().and_then(|path| fs::read_to_string(path).ok()).and_then(|json| serde_json::from_str(&json).ok()).unwrap_or_default()
+}
+
+fn save(tables: &Tables) {
+    let Some(path) = path() else {return};
+    if let Some(dir) = path.parent() {_=fs::create_dir_all(dir);}
+    if let Ok(json) = serde_json::to_string_pretty(tables) {_=fs::write(path, json);}
+}
+
+// Games only compete with games of the same mode & ruleset, rules left at their defaults aren't named so that older tables still count
+fn table_name(args: &crate::Args) -> String {
+    let mode = if args.split_screen {"split-screen"} else if args.host.is_some() || args.connect.is_some() {"versus"} else {"solo"};
+    let mut name = format!("{}, {}, speed {}", mode, args.pieces, args.speed);
+    if args.lock_delay > 0 {name += &format!(", lock delay {}ms", args.lock_delay);}
+    if args.strict_finesse {name += ", strict finesse";}
+    if mode != "solo" && args.attack_table != [0, 1, 2, 4] { //nobody to attack otherwise
+        name += &format!(", attack {}", args.attack_table.iter().map(usize::to_string).collect::<Vec<_>>().join("/"));
+    }
+    name
+}
+
+fn player_name(args: &crate::Args, idx: usize) -> String {
+    match idx {
+        0 => args.name.clone().or_else(|| env::var("USER").ok()).unwrap_or_else(|| String::from("Player 1")),
+        _ => args.name2.clone(),
+    }
+}
+
+// Add the players' results to their table, returns the table to show on the results screen with the new entries highlighted on top, if anyone was playing
+pub fn submit(args: &crate::Args, players: &[Mutex<Player>], pos: [isize; 2]) -> Vec<RenderableObject> {
+    let date = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let mut tables = load();
+    let name = table_name(args);
+    let table = tables.entry(name.clone()).or_default();
+
+    let mut new = Vec::new(); //ranks of the new entries, bots & the AI don't get any
+    for (i, player) in players.iter().enumerate() {
+        let player = player.lock().unwrap();
+        if player.is_bot {continue}
+
+        let rank = table.iter().position(|entry| entry.score < player.score).unwrap_or(table.len());
+        table.insert(rank, Entry {name: player_name(args, i), score: player.score, lines: player.lines, time: player.time().as_secs(), date: date.clone()});
+        for other in new.iter_mut() {if *other >= rank {*other += 1;}}
+        new.push(rank);
+    }
+    if new.is_empty() {return Vec::new()}
+    table.truncate(TABLE_LEN);
+
+// Results screen text, new entries are marked with an arrow
+    let mut rows = vec![format!("HIGH SCORES  {}", name), format!("   {:<3} {:<16} {:>7} {:>6} {:>6}  {}", "#", "NAME", "SCORE", "LINES", "TIME", "DATE")];
+    for (rank, entry) in table.iter().enumerate() {
+        rows.push(format!("{}  {:<3} {:<16} {:>7} {:>6} {:>3}:{:02}  {}",
+            if new.contains(&rank) {'>'} else {' '}, rank+1, entry.name.chars().take(16).collect::<String>(), entry.score, entry.lines, entry.time/60, entry.time%60, entry.date
+        ));
+    }
+    save(&tables);
+
+    let rows: VecDeque<Vec<u8>> = rows.into_iter().map(|row| row.bytes().filter(|b| (0x20..0x7F).contains(b)).collect()).collect();
+    let highlights = new.iter().filter(|&&rank| rank < TABLE_LEN).map(|&rank| {
+        let mut row = RenderableObject::new([pos[0], pos[1] + 2 + rank as isize], VecDeque::from([rows[2 + rank].clone()]), (1,1), false);
+        row.highlighted = true;
+        row
+    }).collect::<Vec<_>>();
+    [RenderableObject::new(pos, rows, (1,1), false)].into_iter().chain(highlights).collect()
+}
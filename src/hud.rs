@@ -0,0 +1,26 @@
+use std::collections::VecDeque;
+
+use crate::{renderable_object::*, player::Player};
+
+pub const WIDTH: usize = 12;
+const LINES: usize = 6;
+const LINES_PER_LEVEL: usize = 10;
+
+pub fn panel(pos: [isize; 2]) -> RenderableObject {RenderableObject::new(pos, VecDeque::from(vec![vec![b' '; WIDTH]; LINES]), (1,1), false)}
+
+impl Player {
+    pub fn update_hud(&mut self) {
+        let secs = self.time().as_secs(); //always counts up, no mode has a time or line limit to count down to
+        let streak = |count: usize| if count > 1 {format!("x{}", count-1)} else {String::from("-")}; //the first one doesn't count
+
+        let lines = [
+            format!("SCORE{:>7}", self.score),
+            format!("LEVEL{:>7}", self.lines/LINES_PER_LEVEL + 1),
+            format!("LINES{:>7}", format!("{}/{}", self.lines%LINES_PER_LEVEL, LINES_PER_LEVEL)),
+            format!("TIME{:>5}:{:02}", secs/60, secs%60),
+            format!("COMBO{:>7}", streak(self.combo)),
+            format!("B2B{:>9}", streak(self.back_to_back)),
+        ];
+        for (row, line) in self.panel.shape.iter_mut().zip(lines) {*row = line.into_bytes();}
+    }
+}
@@ -2,113 +2,223 @@
 mod physics;
 mod rendering;
 mod renderable_object;
+mod piece_set;
+mod versus;
+mod player;
+mod spectate;
+mod bot;
+mod ai;
+mod replay;
+mod highscores;
+mod stats;
+mod finesse;
+mod frame;
+mod theme;
+mod animation;
+mod hud;
+mod terminal;
+mod signals;
+mod save;
+#[cfg(test)]
+mod vt;
 use renderable_object::*;
+use piece_set::{Piece, PieceSet};
+use player::{Controls, Player, Target};
 
-use std::{io, time::Duration, thread, sync::{Arc, Mutex}, collections::VecDeque};
+use std::{fs, io, time::{Duration, Instant}, thread, sync::{Arc, Mutex}, collections::VecDeque};
 use bombs::Bomb;
-use crossterm::{execute, terminal::*, event::*, cursor, style::*};
-use clap::Parser;
+use crossterm::{execute, event::*, style::*};
+use clap::{Parser, Subcommand};
 use rand::Rng;
 
 // Constants
 const PF_WIDTH: usize = 10; //unscaled playfield dimensions
 const PF_HEIGHT: isize = 20;
+const VERSION: &str = env!("CARGO_PKG_VERSION"); //replays, saves, opponents & spectators have to come from the same one
 
 // Console arguments
 #[derive(Parser, Debug)]
 pub struct Args {
-    #[arg(short, long, default_value_t = 15, help = "Framerate at which the game is rendered.")]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(short, long, default_value_t = 15, help = "Highest framerate at which the game is rendered, frames are only drawn when something changed.")]
     framerate: u8,
 
-    #[arg(short, long, default_value_t = String::from("ADQEWS "), help = "Controls, in the format <LEFT><RIGHT><ROTATE_LEFT><ROTATE_RIGHT><HOLD><SOFT_DROP><HARD_DROP>.")]
+    #[arg(short, long, default_value_t = String::from("ADQEWS "), help = "Controls, in the format <LEFT><RIGHT><ROTATE_LEFT><ROTATE_RIGHT><HOLD><SOFT_DROP><HARD_DROP>, or the same as 7 comma-separated key names (e.g. left,right,up,...).")]
     controls: String,
 
     #[arg(short, long, default_value_t = 1., help = "Multiplicative gravity strength modifier. Accepts decimals, non-positive values turn gravity off.")]
     speed: f64,
 
+    #[arg(long, value_name = "MS", default_value_t = 0, help = "How long pieces can still be moved around after landing before they lock.")]
+    lock_delay: u64,
+
     #[arg(short, long, default_value_t = 2, help = "Multiplicative horizontal scale at which the playfield is rendered. Has to be a natural number.")]
     width_scale: u8,
 
     #[arg(short, long, default_value_t = 1, help = "Multiplicative vertical scale at which the playfield is rendered. Has to be a natural number.")]
     vertical_scale: u8,
 
-    #[arg(long, help = "Print out some additional information while playing.")]
-    debug: bool,
+    #[arg(long, help = "Draw two rows per line using half blocks, so that the playfield only takes up half the height. The vertical scale counts half lines then.")]
+    half_blocks: bool,
+
+    #[arg(long, value_enum, help = "Characters to draw with, ascii works on any terminal. Picked according to the locale by default.")]
+    charset: Option<frame::Charset>,
+
+    #[arg(long, default_value_t = String::from("default"), help = "Colours & characters to draw with. Either the name of a built-in theme (default, mono, solarized, gameboy) or the path to a TOML file.")]
+    theme: String,
+
+    #[arg(long, value_enum, help = "Colours the terminal can show, themes get toned down to fit. Detected from $COLORTERM & $TERM by default.")]
+    colours: Option<theme::ColourDepth>,
+
+    #[arg(long, help = "Turn off the line clear, lock & game over animations.")]
+    no_animations: bool,
+
+    #[arg(long, value_name = "MS", default_value_t = 200, help = "How long cleared lines flash before the rows above fall down. Pressing any key skips the rest.")]
+    line_clear_delay: u64,
+
+    #[arg(long, value_name = "FILE", help = "Log what's happening in the game to FILE, for figuring out what went wrong.")]
+    log_file: Option<String>,
+
+    #[arg(long, default_value_t = log::LevelFilter::Info, requires = "log_file", help = "How much to log: error, warn, info, debug (every move & key press) or trace (every frame & gravity tick).")]
+    log_level: log::LevelFilter,
+
+    #[arg(long, help = "Take over the whole terminal with the game centred in it, instead of drawing it below the command line.")]
+    fullscreen: bool,
+
+    #[arg(long, help = "Show a panel with pieces per second, attack per minute, keys per piece & more next to each playfield.")]
+    stats: bool,
+
+    #[arg(long, help = "Count finesse faults, i.e. placements that took more moves & rotations than needed.")]
+    finesse: bool,
+
+    #[arg(long, help = "Same as --finesse, but pieces placed with a fault go back to the top instead of locking.")]
+    strict_finesse: bool,
 
     #[arg(short, long, help = "Disables ghost pieces.")]
     disable_ghost: bool,
+
+    #[arg(short, long, default_value_t = String::from("classic"), help = "Piece set to play with, either a built-in one (classic, pentominoes) or the path to a piece set file.")]
+    pieces: String,
+
+    #[arg(long, value_name = "ADDR", help = "Host a versus game, waiting for an opponent to connect to ADDR (e.g. 0.0.0.0:7878).")]
+    host: Option<String>,
+
+    #[arg(long, value_name = "ADDR", conflicts_with = "host", help = "Connect to a versus game hosted at ADDR.")]
+    connect: Option<String>,
+
+    #[arg(long, value_delimiter = ',', default_values_t = [0, 1, 2, 4], help = "Garbage lines sent to the opponent for clearing 1, 2, 3, ... lines at once. Bigger clears send the last value.")]
+    attack_table: Vec<usize>,
+
+    #[arg(long, conflicts_with_all = ["host", "connect"], help = "Play against a second player on the same keyboard.")]
+    split_screen: bool,
+
+    #[arg(long, default_value_t = String::from("left,right,/,up,.,down,enter"), help = "Controls of the second player in split-screen mode, same format as --controls.")]
+    controls2: String,
+
+    #[arg(long, help = "Give both split-screen players the same sequence of pieces.")]
+    same_seed: bool,
+
+    #[arg(long, value_name = "CMD", help = "Let an external bot speaking the Tetris Bot Protocol (e.g. Cold Clear) play instead of the keyboard.")]
+    bot: Option<String>,
+
+    #[arg(long, value_name = "CMD", requires = "split_screen", help = "Same as --bot, but for the second player in split-screen mode.")]
+    bot2: Option<String>,
+
+    #[arg(long, default_value_t = 2., help = "Pieces per second bots & the autoplayer are allowed to place.")]
+    bot_pps: f64,
+
+    #[arg(long, conflicts_with = "bot", help = "Let the built-in AI play instead of the keyboard.")]
+    autoplay: bool,
+
+    #[arg(long, conflicts_with_all = ["bot", "host", "connect"], help = "Endless demo played by the built-in AI, restarting whenever it tops out. Any key exits.")]
+    demo: bool,
+
+    #[arg(long, value_name = "SECS", default_value_t = 30, help = "Let the built-in AI play a demo when nobody has pressed a key since a solo game started this long ago, until a key is pressed. 0 turns it off.")]
+    idle_demo: u64,
+
+    #[arg(long, value_delimiter = ',', allow_negative_numbers = true, default_values_t = ai::DEFAULT_WEIGHTS, help = "Weights of the built-in AI's heuristic: aggregate height, lines cleared, holes, bumpiness, well depth.")]
+    ai_weights: Vec<f64>,
+
+    #[arg(long, value_name = "ADDR", help = "Broadcast the game to spectators connecting to ADDR (e.g. 0.0.0.0:7879).")]
+    spectate_server: Option<String>,
+
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["host", "connect", "split_screen", "spectate_server", "bot", "autoplay", "demo"], help = "Watch a game broadcast with --spectate-server at ADDR.")]
+    watch: Option<String>,
+
+    #[arg(long, value_name = "FILE", conflicts_with = "watch", help = "Save a replay of the game to FILE when exiting.")]
+    record: Option<String>,
+
+    #[arg(long, conflicts_with_all = ["host", "connect", "watch", "split_screen", "bot", "autoplay", "demo"], help = "Continue the game saved when quitting with Esc the last time, with the piece set, gravity & lock delay it was started with.")]
+    resume: bool,
+
+    #[arg(long, help = "Name to put on the high score table, defaults to your username.")]
+    name: Option<String>,
+
+    #[arg(long, default_value_t = String::from("Player 2"), help = "Name of the second player in split-screen mode.")]
+    name2: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    #[command(about = "Play back a game saved with --record. Space pauses, left/right seek, up/down change the speed.")]
+    Replay {file: String},
 }
 
 // Structs
 #[derive(Debug, Clone)]
 pub struct Block {
     obj: RenderableObject,
-    pivot: [usize; 2]
+    piece: Arc<Piece>,
+    id: usize,
+    rotation: usize,
 }
 impl Block {
-    fn new(shape: Vec<Vec<u8>>, pivot: [usize; 2], scale: (isize, isize), offset: isize) -> Self {
-        Self {obj: RenderableObject::new([3*scale.0+1+offset, 2], VecDeque::from(shape), scale, false), pivot}
+    fn new(id: usize, piece: &Arc<Piece>, palette: &Arc<[Color]>, scale: (isize, isize)) -> Self {
+        let mut obj = RenderableObject::new([spawn_column(piece.rotations[0].len()), 0], VecDeque::from(piece.rotations[piece.spawn].clone()), scale, false);
+        obj.palette = Arc::clone(palette);
+        Self {obj, piece: Arc::clone(piece), id, rotation: piece.spawn}
     }
-    fn new_random(defs: &Arc<[Self; 7]>) -> Self {defs[rand::thread_rng().gen_range(0..7)].clone()}
+    fn new_random(defs: &Arc<Vec<Self>>, rng: &mut impl Rng) -> Self {defs[rng.gen_range(0..defs.len())].clone()}
 
     fn mov(&mut self, x: isize, y: isize, playfield: &RenderableObject) -> CollisionResult {
-        self.obj.pos[0] = self.obj.pos[0] as isize + x;
-        self.obj.pos[1] = self.obj.pos[1] as isize + y;
+        self.obj.pos[0] += x;
+        self.obj.pos[1] += y;
 
         let collision = self.obj.check_collision(playfield);
         if collision != CollisionResult::NoCollision {
-            self.obj.pos[0] = self.obj.pos[0] as isize - x;
-            self.obj.pos[1] = self.obj.pos[1] as isize - y;
+            self.obj.pos[0] -= x;
+            self.obj.pos[1] -= y;
         }
         collision
     }
 
     fn rotate(&mut self, direction: isize, playfield: &RenderableObject) -> CollisionResult {
-        let old_shape = self.obj.shape.clone();
-        let mut new_shape = VecDeque::from(vec![vec![0u8; 4]; 4]);
-
-        for (y, row) in self.obj.shape.iter().enumerate() {
-            for (x, &col) in row.iter().enumerate() {
-                if col == 1 {
-                    new_shape[(self.pivot[1] as isize + (self.pivot[0] - x*2) as isize * (-direction)) as usize / 2]
-                             [(self.pivot[0] as isize + (self.pivot[1] - y*2) as isize * direction) as usize / 2]
-                    = 1;
-                }
+        let (old_shape, old_pos) = (self.obj.shape.clone(), self.obj.pos);
+        let rotation = (self.rotation as isize + direction).rem_euclid(4) as usize;
+
+        self.obj.shape = VecDeque::from(self.piece.rotations[rotation].clone());
+
+        let mut collision = CollisionResult::OutOfBounds;
+        for &[x, y] in self.piece.kicks(self.rotation, rotation) { //try every kick until one fits
+            self.obj.pos = [old_pos[0] + x, old_pos[1] + y];
+
+            collision = self.obj.check_collision(playfield);
+            if collision == CollisionResult::NoCollision {
+                self.rotation = rotation;
+                return collision
             }
         }
 
-        self.obj.shape = new_shape;
-
-        let collision = self.obj.check_collision(playfield);
-        if collision != CollisionResult::NoCollision {self.obj.shape = old_shape;}
+        self.obj.shape = old_shape;
+        self.obj.pos = old_pos;
         collision
     }
 }
 
-struct Controls {
-    left: char,
-    right: char,
-    rotate_left: char,
-    rotate_right: char,
-    hold: char,
-    soft_drop: char,
-    hard_drop: char,
-}
-impl Controls {
-    fn parse(arg_str: &str) -> Self {
-        let str_lowercase = arg_str.to_lowercase();
-        let mut c = str_lowercase.chars();
-        Self {
-            left:         c.next().unwrap(),
-            right:        c.next().unwrap(),
-            rotate_left:  c.next().unwrap(),
-            rotate_right: c.next().unwrap(),
-            hold:         c.next().unwrap(),
-            soft_drop:    c.next().unwrap(),
-            hard_drop:    c.next().unwrap(),
-        }
-    }
-}
+// Board column blocks of the given grid size spawn at
+fn spawn_column(grid: usize) -> isize {(PF_WIDTH as isize - grid as isize)/2}
 
 // Enums
 #[derive(Debug, Clone, PartialEq)]
@@ -117,193 +227,332 @@ pub enum CollisionResult {NoCollision, OutOfBounds, BlockCollision, GameOver}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
 // Argument parsing
-    let args = Arc::new(Args::parse());
+    let mut args = Args::parse();
+
+    // Logging, into a file since the terminal belongs to the game
+    if let Some(path) = &args.log_file {
+        let file = fs::File::create(path).unwrap_or_else(|e| exit_with_error(format!("couldn't create the log file '{}': {}", path, e)));
+        let config = simplelog::ConfigBuilder::new().add_filter_allow_str("tetris_rs").build(); //crossterm's internals aren't interesting
+        _=simplelog::WriteLogger::init(args.log_level, config, file);
+        log::info!("tetris-rs {} started with {:?}", VERSION, std::env::args().skip(1).collect::<Vec<_>>());
+    }
 
-    // Scale
-    if args.vertical_scale == 0 || args.width_scale == 0 {
-        execute!(io::stdout(),
-            SetForegroundColor(Color::Red), SetAttribute(Attribute::Bold), Print("error: "),
-            ResetColor, SetAttribute(Attribute::Reset),                    Print("Scales have to be positive\r\n"),
-        )?;
-        std::process::exit(2)
+    // Replays bring their own players & piece set
+    let replay = args.command.as_ref().map(|Command::Replay {file}| replay::Replay::load(file).unwrap_or_else(|e| exit_with_error(e)));
+    if let Some(replay) = &replay {
+        if args.host.is_some() || args.connect.is_some() || args.split_screen || args.bot.is_some() || args.autoplay || args.demo || args.watch.is_some() || args.record.is_some() || args.resume {
+            exit_with_error("Replays can't be combined with other game modes")
+        }
+        args.strict_finesse = replay.strict_finesse;
     }
-    let scale = (args.width_scale as isize, args.vertical_scale as isize);
-
-    // Controls
-    if args.controls.chars().count() != 7 {
-        execute!(io::stdout(),
-            SetForegroundColor(Color::Red), SetAttribute(Attribute::Bold), Print("error: "),
-            ResetColor, SetAttribute(Attribute::Reset),                    Print("Controls have to be 6 characters long (default examples: '"),
-            SetForegroundColor(Color::DarkYellow),                         Print("ADQEWS "),
-            ResetColor,                                                    Print("' for QWERTY, '"),
-            SetForegroundColor(Color::DarkYellow),                         Print("ASQFWR "),
-            ResetColor,                                                    Print("' for Colemak, '"),
-            SetForegroundColor(Color::DarkYellow),                         Print("AE'.,O "),
-            ResetColor,                                                    Print("' for Dvorak)\r\n"),
-        )?;
-        std::process::exit(2)
+
+    // Saved games bring their own players & rules as well
+    let save = args.resume.then(|| save::Save::load().unwrap_or_else(|e| exit_with_error(e)));
+    if let Some(save) = &save {
+        (args.pieces, args.speed, args.lock_delay, args.strict_finesse) = (save.pieces_name.clone(), save.speed, save.lock_delay, save.strict_finesse);
+        args.split_screen = save.seeds().len() == 2;
     }
-    let controls = Controls::parse(&args.controls);
+    let charset = *args.charset.get_or_insert_with(frame::Charset::detect);
+    if charset == frame::Charset::Ascii && args.half_blocks {exit_with_error("Half blocks need the unicode charset")}
+    let depth = *args.colours.get_or_insert_with(theme::ColourDepth::detect);
+    let theme = Arc::new(theme::Theme::load(&args.theme, depth).unwrap_or_else(|e| exit_with_error(e)));
+    let args = Arc::new(args);
+    let redraw = Arc::new(rendering::Redraw::default()); //how other threads tell the game loop what's changed
+
+    // Scale
+    if args.vertical_scale == 0 || args.width_scale == 0 {exit_with_error("Scales have to be positive")}
+
+    // Controls, nobody plays when watching
+    let mut controls = vec![Controls::parse(&args.controls).unwrap_or_else(|| controls_error())];
+    if args.watch.is_some() {controls.clear();}
+    if args.split_screen {controls.push(Controls::parse(&args.controls2).unwrap_or_else(|| controls_error()));}
+    if let Some(replay) = &replay {controls = replay.seeds().iter().map(|_| Controls::parse(&args.controls).unwrap()).collect();}
+
+    // Piece set
+    let piece_set = match (&replay, &save) {
+        (Some(replay), _) => PieceSet::parse(&replay.pieces),
+        (None, Some(save)) => PieceSet::parse(&save.pieces),
+        (None, None) => PieceSet::load(&args.pieces),
+    }.unwrap_or_else(|e| exit_with_error(e));
+
+    // Versus
+    let link = match (&args.host, &args.connect) {
+        (Some(addr), _) => Some(versus::Link::host(addr, &args, &piece_set)),
+        (None, Some(addr)) => Some(versus::Link::connect(addr, &args, &piece_set)),
+        (None, None) => None,
+    }.transpose().unwrap_or_else(|e| exit_with_error(format!("versus connection failed: {}", e))).map(|link| Arc::new(Mutex::new(link)));
+
+    // Bots
+    if args.bot_pps <= 0. {exit_with_error("Bots have to be allowed to place pieces")}
+    let bots: Vec<Option<bot::Bot>> = [&args.bot, &args.bot2].into_iter().map(|cmd| cmd.as_deref().map(|cmd|
+        bot::Bot::spawn(cmd, &piece_set).unwrap_or_else(|e| exit_with_error(format!("couldn't start bot '{}': {}", bot::Bot::name(cmd), e)))
+    )).collect();
+
+    if args.ai_weights.len() != ai::DEFAULT_WEIGHTS.len() {exit_with_error(format!("The AI needs {} weights", ai::DEFAULT_WEIGHTS.len()))}
+
+    // Spectating
+    let spectate_server = args.spectate_server.as_deref().map(spectate::bind).transpose()
+        .unwrap_or_else(|e| exit_with_error(format!("couldn't start the spectator server: {}", e)));
+    let watch = args.watch.as_deref().map(spectate::watch).transpose()
+        .unwrap_or_else(|e| exit_with_error(format!("couldn't connect to the spectator server: {}", e)));
 
 // Setup
-    enable_raw_mode()?; //handle *all* input manually, including stuff like ctrl+c
-    execute!(io::stdout(), EnableMouseCapture, cursor::Hide)?;
+    let terminal = terminal::Guard::new(args.fullscreen)?;
+    let signals = signals::Signals::register()?;
 
     let (fuse, bomb) = {
         let (fuse, bomb) = Bomb::new(); //used for exiting all threads on program termination
         (Arc::new(Mutex::new(Some(fuse))), bomb)
     };
 
-    let offset = 3+7*args.debug as isize; //x-axis offset of playfield & blocks
-    let block_defs = Arc::new([ //define blocks
-        Block::new(vec![
-            vec![0,0,0,0],
-            vec![1,1,1,1],
-            vec![0,0,0,0],
-            vec![0,0,0,0]
-        ], [3, 3], scale, offset), //all pivot values are multiplied by two, because floats are stupid and I don't want to deal with them if I don't have to
-        Block::new(vec![
-            vec![1,0,0,0],
-            vec![1,1,1,0],
-            vec![0,0,0,0],
-            vec![0,0,0,0]
-        ], [2, 2], scale, offset),
-        Block::new(vec![
-            vec![0,0,1,0],
-            vec![1,1,1,0],
-            vec![0,0,0,0],
-            vec![0,0,0,0]
-        ], [2, 2], scale, offset),
-        Block::new(vec![
-            vec![0,1,1,0],
-            vec![0,1,1,0],
-            vec![0,0,0,0],
-            vec![0,0,0,0]
-        ], [3, 1], scale, offset),
-        Block::new(vec![
-            vec![0,1,1,0],
-            vec![1,1,0,0],
-            vec![0,0,0,0],
-            vec![0,0,0,0]
-        ], [2, 2], scale, offset),
-        Block::new(vec![
-            vec![0,1,0,0],
-            vec![1,1,1,0],
-            vec![0,0,0,0],
-            vec![0,0,0,0]
-        ], [2, 2], scale, offset),
-        Block::new(vec![
-            vec![1,1,0,0],
-            vec![0,1,1,0],
-            vec![0,0,0,0],
-            vec![0,0,0,0]
-        ], [2, 2], scale, offset),
-    ]);
-
-    let current_block = Arc::new(Mutex::new(Block::new_random(&block_defs))); //create a new block; separate objects at first, then permanently drawn onto the playfield once dropped
-    let mut held_block = (Arc::new(Mutex::new(Block::new(vec![vec![0u8; 4]; 4], [3,3], scale, offset + PF_WIDTH as isize*scale.0))), false);
-    held_block.0.lock().unwrap().obj.pos[1] = 0;
-    held_block.0.lock().unwrap().obj.is_bordered = true;
-
-    let objects_to_render = Arc::new(Mutex::new(Vec::with_capacity(4)));
-        objects_to_render.lock().unwrap().push(RenderableObject::new([offset,0], VecDeque::from(vec![vec![0u8; PF_WIDTH]; PF_HEIGHT as usize]), scale, true)); //playfield
-    if args.debug {
-        objects_to_render.lock().unwrap().push(RenderableObject::new([0,2], VecDeque::from(vec![vec![0u8; 1]; 11]), (1,1), false)); //debug text
+    // Players, each with their own playfield & held block, placed next to each other
+    let seed: u64 = rand::thread_rng().gen();
+    let seeds = match (&replay, &save) {
+        (Some(replay), _) => replay.seeds(),
+        (None, Some(save)) => save.seeds(),
+        (None, None) => (0..controls.len() as u64).map(|i| if args.same_seed {seed} else {seed.wrapping_add(i)}).collect(),
+    };
+    let mut offset = 3; //x-axis offset of playfield & blocks
+    let mut players = Vec::with_capacity(controls.len());
+    for (controls, seed) in controls.into_iter().zip(seeds) {
+        let player = Player::new(&args, &redraw, &piece_set, controls, offset, seed);
+        offset = player.right_edge()+2;
+        players.push(player);
     }
+    if let Some(save) = &save {save.restore(&mut players);}
+    if let [p1, p2] = &mut players[..] { //split-screen attacks go straight into the other player's garbage
+        p1.target = Some(Target::Local(Arc::clone(&p2.incoming)));
+        p2.target = Some(Target::Local(Arc::clone(&p1.incoming)));
+    }
+    if let Some(link) = &link {players[0].target = Some(Target::Remote(Arc::clone(link)));}
+    for (player, bot) in players.iter_mut().zip(&bots) {player.is_bot = bot.is_some();}
+    if args.autoplay || args.demo {players[0].is_bot = true;}
+
+    let objects_to_render = Arc::new(Mutex::new(Vec::with_capacity(2)));
+    let opponent_idx = objects_to_render.lock().unwrap().len();
+    if link.is_some() {
+        objects_to_render.lock().unwrap().push(versus::opponent(&players[0])); //opponent's playfield
+    }
+    let status_idx = objects_to_render.lock().unwrap().len();
+    if replay.is_some() {
+        objects_to_render.lock().unwrap().push(replay::status(players[0].bottom())); //replay time & controls
+    }
+    let saveable = replay.is_none() && link.is_none() && watch.is_none() && !players.iter().any(|player| player.is_bot); //only games that are all up to the keyboard
+    log::info!("{} player(s) with seeds {:x?}, {} pieces, speed {}, lock delay {}ms", players.len(), players.iter().map(|player| player.seed).collect::<Vec<_>>(), args.pieces, args.speed, args.lock_delay);
+    let players: Arc<Vec<Mutex<Player>>> = Arc::new(players.into_iter().map(Mutex::new).collect());
 
-// Spawn other threads
-    if args.speed > 0. {physics::thread(
-        Arc::clone(&args),
+// Spawn other threads, only for whatever has to wait on sockets & bot processes
+    if let Some(link) = &link {versus::thread(
         bomb.clone(),
+        Arc::clone(link),
         Arc::clone(&objects_to_render),
-        Arc::clone(&current_block),
-        Arc::clone(&block_defs),
+        opponent_idx,
+        Arc::clone(&players),
         Arc::clone(&fuse)
     );}
-    rendering::thread(
+    for (i, bot) in bots.into_iter().enumerate() {
+        if let Some(bot) = bot {bot::thread(
+            Arc::clone(&args),
+            bomb.clone(),
+            bot,
+            i,
+            Arc::clone(&players),
+            Arc::clone(&fuse)
+        );}
+    }
+    if let Some(listener) = spectate_server {spectate::server_thread(
         Arc::clone(&args),
-        bomb,
+        bomb.clone(),
+        listener,
+        Arc::clone(&players)
+    );}
+    if let Some((reader, palette)) = watch {spectate::viewer_thread(
+        Arc::clone(&args),
+        bomb.clone(),
+        reader,
+        palette,
         Arc::clone(&objects_to_render),
-        Arc::clone(&current_block),
-        Arc::clone(&held_block.0),
-    );
+        Arc::clone(&redraw),
+        Arc::clone(&fuse)
+    );}
+    drop(bomb); //the game loop stops by itself, only the other threads need the signal
+
+// Game loop, input, gravity, the AI, replays & rendering all happen here one after another
+    let mut renderer = rendering::Renderer::new(&args, theme, Arc::clone(&redraw));
+    let mut physics = physics::Physics::new(&args, players.len(), Instant::now());
+    let mut autoplay = (args.autoplay || args.demo).then(|| ai::Autoplay::new(&args, args.demo, Instant::now()));
+    let mut playback = replay.map(|replay| replay::Playback::new(replay, Instant::now()));
+    let mut over = false;
+    let mut idle_demo = (saveable && players.len() == 1 && save.is_none() && args.record.is_none() && args.idle_demo > 0).then(|| ai::IdleDemo::new(&args, Instant::now()));
+    let mut asking = None; //since when the game's been waiting for an answer whether to save it
+    let mut saving = false;
+    let result = loop {
+    // End screen, once anyone decided that the game's over
+        if let Some(titles) = redraw.ended() {
+            renderer.end(&args, &players, &objects_to_render, titles);
+            (physics, over) = (None, true);
+        }
 
-// Input handling
-    loop {
-        match read() { //blocking read
-            Ok(Event::Key(k)) => match k.code {
-                KeyCode::Esc => break,
-                KeyCode::Char(c) => match c.to_ascii_lowercase() {
-                    'c' if k.modifiers.contains(KeyModifiers::CONTROL) => break,
-
-                    ch => {
-                        let cblock = &mut current_block.lock().unwrap();
-                        
-                    // Controls
-                        if ch == controls.left {
-                            cblock.mov(-(scale.0 as isize), 0, &objects_to_render.lock().unwrap()[0]);
-                        } else if ch == controls.right {
-                            cblock.mov(scale.0 as isize, 0, &objects_to_render.lock().unwrap()[0]);
-                        
-                        } else if ch == controls.rotate_left {
-                            cblock.rotate(-1, &objects_to_render.lock().unwrap()[0]);
-                        } else if ch == controls.rotate_right {
-                            cblock.rotate(1, &objects_to_render.lock().unwrap()[0]);
-                        
-                        } else if ch == controls.hold {
-                            let hblock = &mut held_block.0.lock().unwrap();
-                            
-                            if !held_block.1 {
-                                hblock.obj.shape = Block::new_random(&block_defs).obj.shape;
-                                held_block.1 = true;
-                            }
-
-                            std::mem::swap(&mut cblock.obj.shape, &mut hblock.obj.shape);
-                            std::mem::swap(&mut cblock.pivot, &mut hblock.pivot);
-
-                            cblock.obj.pos = block_defs[0].obj.pos;
-                        } else if ch == controls.soft_drop {
-                            cblock.mov(0, scale.1 as isize, &objects_to_render.lock().unwrap()[0]);
-                        } else if ch == controls.hard_drop {
-                            while cblock.mov(0, 1, &objects_to_render.lock().unwrap()[0]) == CollisionResult::NoCollision {}
-
-                            cblock.obj.imprint_to(&mut objects_to_render.lock().unwrap()[0]);
-                            **cblock = Block::new_random(&block_defs);
-
-                            objects_to_render.lock().unwrap()[0].check_line_fills();
+    // Input, waits until something else is due
+        let now = Instant::now();
+        let frame = renderer.next(now);
+        let due = physics.as_ref().map(physics::Physics::next).into_iter()
+            .chain(idle_demo.as_ref().filter(|_| asking.is_none() && !over).and_then(ai::IdleDemo::next))
+            .chain(autoplay.as_ref().filter(|_| !over).map(ai::Autoplay::next))
+            .fold(frame, Instant::min).max(now);
+        match poll(due - now).and_then(|ready| ready.then(read).transpose()) {
+            Ok(None) => (),
+            Ok(Some(Event::Key(k))) => {
+                log::debug!("key {:?} {:?}", k.code, k.modifiers);
+
+            // Idle demo, any key but Esc hands the player a new game
+                if let Some(demo) = &mut idle_demo {
+                    if k.code == KeyCode::Esc && demo.running() {break Ok(())}
+                    if demo.key(&args, &piece_set, &players, &objects_to_render, Instant::now()) {
+                        physics = physics::Physics::new(&args, players.len(), Instant::now());
+                        redraw.request();
+                        continue
+                    }
+                }
+                let code = match k.code {
+                    KeyCode::Char('z') if k.modifiers.contains(KeyModifiers::CONTROL) => {signals::ctrl_z(); continue}
+                    _ if args.demo => break Ok(()),
+                    KeyCode::Esc => match asking.take() {
+                        Some(since) => { //back to the game
+                            objects_to_render.lock().unwrap().pop();
+                            pause(&mut physics, &mut idle_demo, &mut autoplay, &mut playback, &players, Instant::now() - since);
+                            for player in players.iter() {player.lock().unwrap().thaw_clock();}
+                            redraw.request();
+                            continue
                         }
-
-                    // Debug text
-                        if args.debug {
-                            objects_to_render.lock().unwrap()[1].shape[3] = format!("{:#?} {}", k.modifiers, ch).bytes().collect();
-                            
-                            let mut i=1;
-                            for (y, row) in cblock.obj.shape.iter().enumerate() {
-                                for (x, &col) in row.iter().enumerate() {
-                                    if col==1 {
-                                        objects_to_render.lock().unwrap()[1].shape[6+i] = format!("x:{} y:{}", cblock.obj.pos[0]+cblock.obj.scale.0*x as isize, cblock.obj.pos[1]+cblock.obj.scale.1*y as isize).bytes().collect();
-                                        i+=1;
-                                    }
-                                }
-                            }
+                        None if saveable && !over => {
+                            let banner = rendering::banner(&players[0].lock().unwrap().playfield, b"SAVE & QUIT?", b" Y / N / Esc");
+                            objects_to_render.lock().unwrap().push(banner);
+                            for player in players.iter() {player.lock().unwrap().freeze_clock();} //nothing counts the time spent deciding
+                            asking = Some(Instant::now());
+                            redraw.request();
+                            continue
                         }
+                        None => break Ok(()),
                     }
+                    KeyCode::Char('c') if k.modifiers.contains(KeyModifiers::CONTROL) => break Ok(()),
+                    KeyCode::Char('y' | 'Y') if asking.is_some() => {saving = true; break Ok(())}
+                    KeyCode::Char('n' | 'N') if asking.is_some() => break Ok(()),
+                    _ if asking.is_some() => continue,
+                    KeyCode::Char(c) => KeyCode::Char(player::fold(c)),
+                    code => code,
+                };
+
+            // Controls, nothing to control anymore after the game's over
+                if over {renderer.skip(&players); continue}
+                if let Some(playback) = &mut playback {playback.handle_key(code); redraw.request(); continue}
+                for (i, player) in players.iter().enumerate() {
+                    let topped_out = {
+                        let player = &mut player.lock().unwrap();
+                        if player.rows_pending() { //any key skips line clears, not only the controls
+                            player.finish_clear();
+                            redraw.request();
+                        }
+                        player.handle_key(code)
+                    };
+                    if topped_out == Some(true) {rendering::lost(&players, &fuse, i);}
                 }
-                _ => ()
             }
-            Ok(_) => (),
-            Err(_) => break
+            Ok(Some(Event::Resize(w, h))) => {
+                log::debug!("terminal resized to {}x{}", w, h);
+                redraw.request();
+            }
+            Ok(Some(_)) => (),
+            Err(_) => break Ok(())
         };
-    }
+
+    // Signals, Ctrl+Z included
+        match signals.take() {
+            Some(signals::Signal::Quit) => {log::info!("quitting on a signal"); break Ok(())}
+            Some(signal @ (signals::Signal::Suspend | signals::Signal::Resumed)) => { //nothing moves while the process is stopped
+                let paused = match signal {
+                    signals::Signal::Suspend => {
+                        terminal.suspend();
+                        signals.stop()
+                    }
+                    _ => Instant::now().saturating_duration_since(due), //stopped from outside, most likely while waiting for input, so however long that overslept
+                };
+                log::info!("suspended for {:?}", paused);
+                pause(&mut physics, &mut idle_demo, &mut autoplay, &mut playback, &players, paused);
+                if let Some(since) = &mut asking {*since += paused;} //already paused for that
+                if let Err(e) = terminal.resume() {break Err(e.into())}
+                renderer.forget();
+            }
+            None => (),
+        }
+
+    // Gravity, lock delay, the AI's moves & replays
+        let now = Instant::now();
+        if let Some(autoplay) = autoplay.as_mut().filter(|_| !over) {
+            if autoplay.update(&args, &players[0], now) {rendering::lost(&players, &fuse, 0);}
+        }
+        if let Some(playback) = &mut playback {playback.update(&args, &redraw, &players, &objects_to_render, status_idx, now);}
+        if let Some(demo) = idle_demo.as_mut().filter(|_| asking.is_none() && !over) {demo.update(&args, &piece_set, &players, &objects_to_render, now);}
+        if let Some(loser) = physics.as_mut().filter(|_| asking.is_none()).and_then(|physics| physics.update(&players, now)) {rendering::lost(&players, &fuse, loser);}
+
+    // Only draws if anything changed
+        if let Err(e) = renderer.update(&args, &players, &objects_to_render, now) {break Err(e)}
+    };
 
 // Cleanup
     if let Some(_fuse) = std::mem::take(&mut *fuse.lock().unwrap()) { //if closed manually instead of CollisionResult::GameOver
         let fire = _fuse.light(());                                       //send close signal
         while !fire.extinguished() {thread::sleep(Duration::from_millis(1))} //wait until all threads are closed
-    } else {execute!(io::stdout().lock(), cursor::MoveDown(2), Clear(ClearType::FromCursorDown))?;}
+    }
+    drop(terminal);
+    for (i, player) in players.iter().enumerate() {
+        let player = player.lock().unwrap();
+        log::info!("player {} ended with score {}, {} lines & {} pieces in {:?}", i+1, player.score, player.lines, player.placed, player.time());
+    }
 
-    disable_raw_mode()?;
-    execute!(io::stdout(), DisableMouseCapture, cursor::Show)?;
+    if let Some(path) = &args.record {
+        replay::Replay::record(&args, &piece_set, &players).save(path).unwrap_or_else(|e| exit_with_error(format!("couldn't save the replay to '{}': {}", path, e)));
+    }
+
+    if saving {
+        log::info!("saving the game");
+        save::Save::take(&args, &piece_set, &players).write().unwrap_or_else(|e| exit_with_error(format!("couldn't save the game: {}", e)));
+    } else if save.is_some() {
+        save::Save::remove();
+    }
 
+    result.unwrap_or_else(|e| exit_with_error(e));
     Ok(())
 }
+
+// Nothing moves while the process is stopped or the game waits for an answer
+fn pause(physics: &mut Option<physics::Physics>, idle_demo: &mut Option<ai::IdleDemo>, autoplay: &mut Option<ai::Autoplay>, playback: &mut Option<replay::Playback>, players: &[Mutex<Player>], by: Duration) {
+    if let Some(physics) = physics {physics.delay(by);}
+    if let Some(demo) = idle_demo {demo.delay(by);}
+    if let Some(autoplay) = autoplay {autoplay.delay(by);}
+    if let Some(playback) = playback {playback.delay(by);}
+    for player in players.iter() {player.lock().unwrap().pause(by);}
+}
+
+fn controls_error() -> ! {
+    _=execute!(io::stdout(),
+        SetForegroundColor(Color::Red), SetAttribute(Attribute::Bold), Print("error: "),
+        ResetColor, SetAttribute(Attribute::Reset),                    Print("Controls have to be 7 characters long, or 7 comma-separated key names (default examples: '"),
+        SetForegroundColor(Color::DarkYellow),                         Print("ADQEWS "),
+        ResetColor,                                                    Print("' for QWERTY, '"),
+        SetForegroundColor(Color::DarkYellow),                         Print("ASQFWR "),
+        ResetColor,                                                    Print("' for Colemak, '"),
+        SetForegroundColor(Color::DarkYellow),                         Print("AE'.,O "),
+        ResetColor,                                                    Print("' for Dvorak)\r\n"),
+    );
+    std::process::exit(2)
+}
+
+fn exit_with_error(msg: impl std::fmt::Display) -> ! {
+    log::error!("{}", msg);
+    _=execute!(io::stdout(),
+        SetForegroundColor(Color::Red), SetAttribute(Attribute::Bold), Print("error: "),
+        ResetColor, SetAttribute(Attribute::Reset),                    Print(msg), Print("\r\n"),
+    );
+    std::process::exit(2)
+}
@@ -1,59 +1,124 @@
+use std::{sync::Mutex, time::{Duration, Instant}};
 
-use std::{thread, sync::{Arc, Mutex}, time::Duration};
+use crate::player::Player;
 
-use bombs::Fuse;
+const DEFAULT_SPEED: f64 = 5E+8; //nanosecs between updates
 
-use crate::{renderable_object::*, CollisionResult, Block, PF_HEIGHT, PF_WIDTH};
+// Fixed timestep, ticks stay on their schedule however late the game loop gets around to them
+pub struct Timer {
+    step: Duration,
+    next: Instant,
+}
+impl Timer {
+    pub fn new(step: Duration, now: Instant) -> Self {Self {step, next: now + step}}
 
-const DEFAULT_SPEED: f64 = 5E+8; //nanosecs between updates
+    pub fn next(&self) -> Instant {self.next}
+
+    pub fn delay(&mut self, by: Duration) {self.next += by;}
+
+    // Whether a tick is due, call it until it returns false to catch up on missed ones
+    pub fn tick(&mut self, now: Instant) -> bool {
+        if now < self.next {return false}
+        self.next += self.step;
+        true
+    }
+}
+
+// Gravity & lock delay for every player
+pub struct Physics {
+    gravity: Timer,
+    lock_delay: Duration,
+    locks: Vec<Option<(Instant, usize)>>, //when each player's resting piece locks & which piece that is
+}
+impl Physics {
+    // Nothing falls without gravity or while watching a replay
+    pub fn new(args: &crate::Args, players: usize, now: Instant) -> Option<Self> {
+        (args.speed > 0. && players > 0 && !matches!(args.command, Some(crate::Command::Replay {..}))).then(|| Self {
+            gravity: Timer::new(Duration::from_nanos((DEFAULT_SPEED/args.speed) as u64), now),
+            lock_delay: Duration::from_millis(args.lock_delay),
+            locks: vec![None; players],
+        })
+    }
 
-pub fn thread(
-    args: Arc<crate::Args>,
-    bomb: bombs::Bomb<()>,
-    objects: Arc<Mutex<Vec<RenderableObject>>>,
-    current_block: Arc<Mutex<crate::Block>>,
-    block_defs: Arc<[Block; 7]>,
-    fuse: Arc<Mutex<Option<Fuse<()>>>>
-) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-    // Main loop
-        let scale = current_block.lock().unwrap().obj.scale;
-        while bomb.exploded().is_none() { //check for close signal
-        // Wait between updates
-            thread::sleep(Duration::from_nanos((DEFAULT_SPEED/args.speed) as u64));
-
-        // Do stuff
-            let cblock = &mut current_block.lock().unwrap();
-
-            if cblock.obj.check_collision(&objects.lock().unwrap()[0]) == CollisionResult::GameOver { //gameover check
-                if let Some(_fuse) = std::mem::take(&mut *fuse.lock().unwrap()) {
-                    _=_fuse.light(());
-
-                    thread::sleep(Duration::from_nanos(1_000_000_000/args.framerate as u64)); //wait 2 frames for rendering thread to close
-
-                    let mut stdoutl = std::io::stdout().lock();
-                    let playfield = &mut objects.lock().unwrap()[0];
-                    
-                    crossterm::execute!(std::io::stdout().lock(), crossterm::cursor::MoveUp(1)).unwrap();
-                    playfield.render(&mut stdoutl);
-
-                    RenderableObject::new([playfield.pos[0]+PF_WIDTH as isize/2*playfield.scale.0-6, PF_HEIGHT/2*playfield.scale.1-2], std::collections::VecDeque::from(vec![
-                        Vec::from(*b" GAME  OVER "),
-                        Vec::from(*b"------------"),
-                        Vec::from(*b"Esc to exit."),
-                    ]), (1,1), true).render(&mut stdoutl);
-                    break;
+    // Nothing falls while the game is paused
+    pub fn delay(&mut self, by: Duration) {
+        self.gravity.delay(by);
+        for (at, _) in self.locks.iter_mut().flatten() {*at += by;}
+    }
+
+    pub fn next(&self) -> Instant {self.locks.iter().flatten().map(|&(at, _)| at).fold(self.gravity.next(), Instant::min)}
+
+    // Do whatever is due, returns the player that topped out if anyone did
+    pub fn update(&mut self, players: &[Mutex<Player>], now: Instant) -> Option<usize> {
+        for (i, (player, lock)) in players.iter().zip(self.locks.iter_mut()).enumerate() {
+            let player = &mut player.lock().unwrap();
+            if let Some((at, piece)) = *lock { //locks only count for the piece that landed, the next one gets its own delay
+                if piece != player.placed || !player.resting() {*lock = None;}
+                else if now >= at {
+                    *lock = None;
+                    if player.gravity() {return Some(i)}
                 }
             }
-            if cblock.mov(0, scale.1 as isize, &objects.lock().unwrap()[0]) != CollisionResult::NoCollision { //move down
-                cblock.obj.imprint_to(&mut objects.lock().unwrap()[0]);
-                **cblock = Block::new_random(&block_defs);
-                objects.lock().unwrap()[0].check_line_fills();
+        }
+
+        while self.gravity.tick(now) {
+            log::trace!("gravity tick {:?} late", now - (self.gravity.next() - self.gravity.step));
+            for (i, (player, lock)) in players.iter().zip(self.locks.iter_mut()).enumerate() {
+                let player = &mut player.lock().unwrap();
+                if lock.is_some() {continue}
+                if !self.lock_delay.is_zero() && !player.is_bot && !player.rows_pending() && player.resting() { //a bit more time to move it around
+                    *lock = Some((self.gravity.next() - self.gravity.step + self.lock_delay, player.placed));
+                    log::trace!("player {} landed, locking in {:?}", i+1, self.lock_delay);
+                    continue
+                }
+                if player.gravity() {return Some(i)}
             }
         }
-    
-    // Exit
-        //let mut stdoutl = io::stdout().lock();
-        //_=write!(stdoutl, "Exiting...\r\n"); _=stdoutl.flush();
-    })
-}
\ No newline at end of file
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    const STEP: Duration = Duration::from_millis(500);
+
+    fn game(lock_delay: u64, start: Instant) -> (Physics, [Mutex<Player>; 1]) {
+        let args = crate::Args::parse_from(["tetris-rs", "--lock-delay", &lock_delay.to_string()]);
+        let player = crate::player::test_player(&args, 7);
+        (Physics::new(&args, 1, start).unwrap(), [Mutex::new(player)])
+    }
+
+    // However late the loop gets around to it, the same ticks happen
+    #[test]
+    fn catches_up() {
+        let start = Instant::now();
+        let (mut steady, steady_players) = game(0, start);
+        let (mut late, late_players) = game(0, start);
+        for tick in 1..=60 {steady.update(&steady_players, start + STEP*tick);}
+        late.update(&late_players, start + STEP*60);
+
+        let (steady, late) = (steady_players[0].lock().unwrap(), late_players[0].lock().unwrap());
+        assert!(steady.placed > 0);
+        assert_eq!((steady.placed, steady.board()), (late.placed, late.board()));
+    }
+
+    #[test]
+    fn lock_delay() {
+        let start = Instant::now();
+        let (mut physics, players) = game(1200, start);
+        let landed = (1..100).map(|tick| start + STEP*tick).find(|&now| {
+            physics.update(&players, now);
+            players[0].lock().unwrap().resting()
+        }).unwrap();
+
+        physics.update(&players, landed + STEP*2); //would've locked on the next tick without the delay
+        assert_eq!(players[0].lock().unwrap().placed, 0);
+        physics.update(&players, landed + STEP + Duration::from_millis(1199)); //the delay starts on that tick
+        assert_eq!(players[0].lock().unwrap().placed, 0);
+        physics.update(&players, landed + STEP + Duration::from_millis(1200));
+        assert_eq!(players[0].lock().unwrap().placed, 1);
+    }
+}
@@ -0,0 +1,205 @@
+use std::{collections::HashMap, fs, sync::Arc};
+use crossterm::style::Color;
+use serde::Deserialize;
+
+use crate::{renderable_object::PIECE_CELL, PF_WIDTH};
+
+pub const BUILTIN: [(&str, &str); 2] = [
+    ("classic",     include_str!("../pieces/classic.toml")),
+    ("pentominoes", include_str!("../pieces/pentominoes.toml")),
+];
+const ROTATIONS: [char; 4] = ['0', 'R', '2', 'L']; //clockwise order
+const MAX_PIECES: usize = (0x20 - PIECE_CELL) as usize; //cell values from 0x20 up are ASCII text
+
+// File format
+#[derive(Deserialize)]
+struct SetDef {
+    #[serde(default)]
+    kicks: HashMap<String, HashMap<String, Vec<[isize; 2]>>>,
+    pieces: Vec<PieceDef>,
+}
+
+#[derive(Deserialize)]
+struct PieceDef {
+    name: String,
+    shape: Vec<String>,
+    pivot: [f64; 2],
+    colour: Color,
+    #[serde(default = "default_spawn")]
+    spawn: String,
+    kicks: Option<String>,
+}
+fn default_spawn() -> String {String::from("0")}
+
+// Loaded piece sets
+#[derive(Debug)]
+pub struct Piece {
+    pub name: String,
+    pub colour: Color,
+    pub rotations: [Vec<Vec<u8>>; 4], //shape in every orientation, all the size of the set's grid
+    pub spawn: usize,
+    kicks: HashMap<(usize, usize), Vec<[isize; 2]>>, //y points down here, unlike in the file
+}
+impl Piece {
+    pub fn kicks(&self, from: usize, to: usize) -> &[[isize; 2]] {
+        self.kicks.get(&(from, to)).map_or(&[[0, 0]], Vec::as_slice)
+    }
+}
+
+#[derive(Debug)]
+pub struct PieceSet {
+    pub grid: usize,
+    pub pieces: Vec<Arc<Piece>>,
+    pub source: String, //what it was parsed from, replays carry it along
+}
+impl PieceSet {
+    pub fn load(name_or_path: &str) -> Result<Self, String> {
+        match BUILTIN.iter().find(|(name, _)| *name == name_or_path) {
+            Some((_, src)) => Self::parse(src),
+            None => Self::parse(&fs::read_to_string(name_or_path).map_err(|e| format!("couldn't read piece set '{}': {}", name_or_path, e))?),
+        }
+    }
+
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let def: SetDef = toml::from_str(src).map_err(|e| format!("invalid piece set: {}", e))?;
+        if def.pieces.is_empty() || def.pieces.len() > MAX_PIECES {
+            return Err(format!("piece sets have to contain between 1 and {} pieces", MAX_PIECES))
+        }
+
+    // Grid size, every piece gets padded to a square of this size
+        let grid = def.pieces.iter()
+            .flat_map(|p| p.shape.iter().map(|row| row.chars().count()).chain([p.shape.len()]))
+            .max().unwrap_or(0);
+        if grid > PF_WIDTH {return Err(format!("pieces can't be wider than the playfield ({} cells)", PF_WIDTH))}
+
+        let mut pieces = Vec::with_capacity(def.pieces.len());
+        for (id, p) in def.pieces.iter().enumerate() {
+            let err = |msg: String| format!("piece '{}': {}", p.name, msg);
+
+        // Shape
+            let mut cells = Vec::new();
+            for (y, row) in p.shape.iter().enumerate() {
+                for (x, ch) in row.chars().enumerate() {
+                    match ch {
+                        '#' => cells.push([x as isize, y as isize]),
+                        '.' | ' ' => (),
+                        _ => return Err(err(format!("unknown character '{}' in shape, use '#' and '.'", ch))),
+                    }
+                }
+            }
+            if cells.is_empty() {return Err(err(String::from("shape is empty")))}
+
+        // Rotations, pivot values are doubled so that they can sit between cells
+            let pivot = [(p.pivot[0]*2.).round() as isize, (p.pivot[1]*2.).round() as isize];
+            if p.pivot.iter().zip(pivot).any(|(&f, i)| f*2. != i as f64) || (pivot[0]+pivot[1]) % 2 != 0 {
+                return Err(err(String::from("pivot has to lie on the centre or the corner of a cell")))
+            }
+
+            let mut rotations: [Vec<Vec<u8>>; 4] = Default::default();
+            for rotation in rotations.iter_mut() {
+                *rotation = vec![vec![0u8; grid]; grid];
+                for &[x, y] in cells.iter() {
+                    if x < 0 || y < 0 || x >= grid as isize || y >= grid as isize {
+                        return Err(err(format!("pivot rotates cells outside of the {}x{} grid", grid, grid)))
+                    }
+                    rotation[y as usize][x as usize] = PIECE_CELL + id as u8;
+                }
+                for cell in cells.iter_mut() { //rotate clockwise
+                    *cell = [(pivot[0]+pivot[1] - 2*cell[1])/2, (pivot[1]-pivot[0] + 2*cell[0])/2];
+                }
+            }
+
+        // Spawn orientation & kicks
+            let spawn = parse_rotation(&p.spawn).ok_or_else(|| err(format!("unknown spawn orientation '{}'", p.spawn)))?;
+
+            let mut kicks = HashMap::new();
+            if let Some(table_name) = &p.kicks {
+                let table = def.kicks.get(table_name).ok_or_else(|| err(format!("unknown kick table '{}'", table_name)))?;
+                for (transition, offsets) in table.iter() {
+                    let mut chars = transition.chars().map(|c| parse_rotation(&c.to_string()));
+                    match (chars.next().flatten(), chars.next().flatten(), chars.next()) {
+                        (Some(from), Some(to), None) if from != to => {
+                            kicks.insert((from, to), offsets.iter().map(|&[x, y]| [x, -y]).collect());
+                        }
+                        _ => return Err(format!("kick table '{}': invalid transition '{}', expected something like '0R'", table_name, transition)),
+                    }
+                }
+            }
+
+            pieces.push(Arc::new(Piece {name: p.name.clone(), colour: p.colour, rotations, spawn, kicks}));
+        }
+
+        Ok(Self {grid, pieces, source: String::from(src)})
+    }
+
+    pub fn palette(&self) -> Arc<[Color]> {self.pieces.iter().map(|p| p.colour).collect()}
+}
+
+fn parse_rotation(s: &str) -> Option<usize> {
+    ROTATIONS.iter().position(|&r| s.eq_ignore_ascii_case(&r.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piece(name: &str, shape: &[&str], pivot: [f64; 2]) -> String {
+        format!("[[pieces]]\nname = {:?}\nshape = {:?}\npivot = {:?}\ncolour = \"red\"\n", name, shape, pivot)
+    }
+
+    fn cells(rows: &[Vec<u8>]) -> Vec<[usize; 2]> {
+        (0..rows.len()).flat_map(|y| (0..rows[y].len()).map(move |x| [x, y])).filter(|&[x, y]| rows[y][x] != 0).collect()
+    }
+
+    fn shape(rows: &[&str]) -> Vec<[usize; 2]> {
+        cells(&rows.iter().map(|row| row.bytes().map(|c| (c == b'#') as u8).collect()).collect::<Vec<_>>())
+    }
+
+    // Rotating clockwise around the pivots has to end up in the same places as SRS
+    #[test]
+    fn classic_is_srs() {
+        let set = PieceSet::load("classic").unwrap();
+        let srs: [(&str, [&[&str]; 2]); 7] = [
+            ("I", [&["..#.", "..#.", "..#.", "..#."], &["....", "....", "####", "...."]]),
+            ("J", [&[".##", ".#.", ".#."], &["...", "###", "..#"]]),
+            ("L", [&[".#.", ".#.", ".##"], &["...", "###", "#.."]]),
+            ("O", [&[".##.", ".##."], &[".##.", ".##."]]),
+            ("S", [&[".#.", ".##", "..#"], &["...", ".##", "##."]]),
+            ("T", [&[".#.", ".##", ".#."], &["...", "###", ".#."]]),
+            ("Z", [&["..#", ".##", ".#."], &["...", "##.", ".##"]]),
+        ];
+        for (name, rotations) in srs {
+            let piece = set.pieces.iter().find(|piece| piece.name == name).unwrap();
+            for (rotation, expected) in rotations.iter().enumerate() {
+                assert_eq!(cells(&piece.rotations[rotation+1]), shape(expected), "{} in {}", name, ROTATIONS[rotation+1]);
+            }
+        }
+    }
+
+    // Kick tables have y pointing up like in the SRS docs, the game has it pointing down
+    #[test]
+    fn kicks_flip_y() {
+        let set = PieceSet::load("classic").unwrap();
+        let t = set.pieces.iter().find(|piece| piece.name == "T").unwrap();
+        assert_eq!(t.kicks(0, 1), &[[0, 0], [-1, 0], [-1, -1], [0, 2], [-1, 2]]);
+        assert_eq!(t.kicks(0, 2), &[[0, 0]]); //no table for 180s
+    }
+
+    #[test]
+    fn invalid_sets() {
+        let errors = [
+            (piece("A", &["##"], [0.25, 0.]), "pivot has to lie"),
+            (piece("A", &["##"], [0.5, 0.]), "pivot has to lie"), //halfway along an edge
+            (piece("A", &["##", ".."], [0., 0.]), "outside of the 2x2 grid"),
+            (piece("A", &["###########"], [5., 0.]), "wider than the playfield"),
+            (piece("A", &["#x"], [0., 0.]), "unknown character"),
+            (String::from("pieces = []"), "between 1 and"),
+            ((0..=MAX_PIECES).map(|i| piece(&i.to_string(), &["#"], [0., 0.])).collect(), "between 1 and"),
+        ];
+        for (src, error) in errors {
+            let result = PieceSet::parse(&src);
+            assert!(result.as_ref().is_err_and(|e| e.contains(error)), "{:?} instead of '{}' for\n{}", result.map(|set| set.grid), error, src);
+        }
+        assert_eq!(PieceSet::parse(&(0..MAX_PIECES).map(|i| piece(&i.to_string(), &["#"], [0., 0.])).collect::<String>()).map(|set| set.pieces.len()), Ok(MAX_PIECES));
+    }
+}
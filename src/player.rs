@@ -0,0 +1,443 @@
+use std::{sync::{Arc, Mutex, atomic::{AtomicUsize, Ordering}}, collections::VecDeque, time::{Duration, Instant}};
+use crossterm::event::KeyCode;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde::{Serialize, Deserialize};
+
+use crate::{renderable_object::*, frame::Frame, piece_set::{Piece, PieceSet}, finesse::Finesse, animation::Animations, versus::Link, Block, CollisionResult, PF_HEIGHT, PF_WIDTH};
+
+const QUEUE_LEN: usize = 5; //upcoming pieces that are already decided
+const SCORES: [usize; 5] = [0, 100, 300, 500, 800]; //points for clearing 0, 1, 2, ... lines at once, bigger clears get the last value
+
+#[derive(Clone)]
+pub struct Controls {
+    left: KeyCode,
+    right: KeyCode,
+    rotate_left: KeyCode,
+    rotate_right: KeyCode,
+    hold: KeyCode,
+    soft_drop: KeyCode,
+    hard_drop: KeyCode,
+}
+impl Controls {
+    // Either exactly 7 characters, or 7 comma-separated key names
+    pub fn parse(arg_str: &str) -> Option<Self> {
+        let keys: Vec<KeyCode> = if arg_str.chars().count() == 7 {
+            arg_str.chars().map(|c| KeyCode::Char(fold(c))).collect()
+        } else {
+            arg_str.split(',').map(parse_key).collect::<Option<_>>()?
+        };
+        if keys.len() != 7 {return None}
+
+        Some(Self {
+            left:         keys[0],
+            right:        keys[1],
+            rotate_left:  keys[2],
+            rotate_right: keys[3],
+            hold:         keys[4],
+            soft_drop:    keys[5],
+            hard_drop:    keys[6],
+        })
+    }
+}
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    let name = name.trim();
+    Some(match name.to_ascii_lowercase().as_str() {
+        "left" => KeyCode::Left, "right" => KeyCode::Right, "up" => KeyCode::Up, "down" => KeyCode::Down,
+        "enter" => KeyCode::Enter, "space" => KeyCode::Char(' '), "tab" => KeyCode::Tab, "backspace" => KeyCode::Backspace,
+        "insert" => KeyCode::Insert, "delete" => KeyCode::Delete, "home" => KeyCode::Home, "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp, "pagedown" => KeyCode::PageDown,
+        _ if name.chars().count() == 1 => KeyCode::Char(fold(name.chars().next().unwrap())),
+        _ => return None,
+    })
+}
+
+// Keys match regardless of case, folded the same way for the controls & the key presses
+pub fn fold(c: char) -> char {c.to_lowercase().next().unwrap_or(c)}
+
+// Everything that changes a player's game, recorded so that it can be replayed from the seed alone
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Left,
+    Right,
+    RotateLeft,
+    RotateRight,
+    Hold,
+    SoftDrop,
+    HardDrop,
+    Gravity,
+    Place {rotation: usize, x: isize, y: isize}, //bots teleport their pieces, x is relative to the playfield
+    Garbage {lines: usize},                      //recorded when it rises, so replays don't depend on the opponent
+    Restart,
+}
+
+// Where a player's attacks end up
+pub enum Target {
+    Local(Arc<AtomicUsize>), //other player's incoming garbage
+    Remote(Arc<Mutex<Link>>),
+}
+
+pub struct Player {
+    pub playfield: RenderableObject,
+    pub current_block: Block, //separate object at first, then permanently drawn onto the playfield once dropped
+    pub queue: VecDeque<Block>,
+    held_block: Block,
+    has_held: bool,
+    hud: [RenderableObject; 3], //HELD text, incoming garbage text & finesse faults
+    pub panel: RenderableObject, //score, level, lines, time, combo & back-to-back, see hud.rs
+    pub stats: Option<RenderableObject>, //panel under the hold box, see stats.rs
+    pub finesse: Option<Finesse>,
+    pub animations: Option<Animations>, //see animation.rs
+    pub controls: Controls,
+    pub defs: Arc<Vec<Block>>,
+    pub drawn: usize, //pieces taken out of the queue so far
+    pub lines: usize, //cleared so far
+    pub score: usize,
+    pub combo: usize,        //pieces in a row that cleared lines
+    pub back_to_back: usize, //line clears in a row that were tetrises (or bigger)
+    pub placed: usize,
+    pub attack: usize, //garbage lines sent, including cancelled ones
+    pub keys: usize,   //key presses that were one of the controls
+    pub histogram: Vec<usize>, //pieces placed by id
+    rng: StdRng,
+    garbage_rng: StdRng, //holes in garbage lines, apart from the pieces so that --same-seed players keep getting the same ones
+    attack_table: Vec<usize>,
+    pub incoming: Arc<AtomicUsize>, //garbage received, but not yet added to the playfield
+    pub target: Option<Target>,
+    pub is_bot: bool, //ignores the keyboard & gravity, see bot.rs & ai.rs
+    pub seed: u64,
+    pub events: Vec<(u64, Action)>, //milliseconds since the start & what happened, see replay.rs
+    start: Instant,
+    played: Duration,        //before the game was saved, see save.rs
+    clock: Option<Duration>, //replays keep the time themselves
+    pub redraw: Arc<crate::rendering::Redraw>, //shared with the game loop
+}
+// Test setup, a game of --pieces (classic by default) with the default controls
+#[cfg(test)]
+pub fn test_player(args: &crate::Args, seed: u64) -> Player {
+    Player::new(args, &Default::default(), &PieceSet::load(&args.pieces).unwrap(), Controls::parse(&args.controls).unwrap(), 3, seed)
+}
+
+impl Player {
+    pub fn new(args: &crate::Args, redraw: &Arc<crate::rendering::Redraw>, piece_set: &PieceSet, controls: Controls, offset: isize, seed: u64) -> Self {
+        let scale = (args.width_scale as isize, args.vertical_scale as isize);
+        let palette = piece_set.palette();
+        let defs = Arc::new(piece_set.pieces.iter().enumerate() //define blocks
+            .map(|(id, piece)| {
+                let mut block = Block::new(id, piece, &palette, scale);
+                block.obj.half = args.half_blocks;
+                block
+            })
+            .collect::<Vec<_>>());
+
+        let mut playfield = RenderableObject::new([offset,0], VecDeque::from(vec![vec![0u8; PF_WIDTH]; PF_HEIGHT as usize]), scale, true);
+        playfield.half = args.half_blocks;
+        playfield.palette = palette;
+
+        let mut held_block = Block::new(0, &piece_set.pieces[0], &playfield.palette, scale);
+        held_block.obj.pos = [offset + (PF_WIDTH as isize + crate::spawn_column(piece_set.grid))*scale.0 + 1, 0]; //on screen, next to the playfield
+        held_block.obj.is_bordered = true;
+        held_block.obj.half = args.half_blocks;
+        held_block.obj.shape = VecDeque::from(vec![vec![0u8; piece_set.grid]; piece_set.grid]);
+
+        let hud = [
+            RenderableObject::new([held_block.obj.pos[0]+1, 0], VecDeque::from(vec![match args.width_scale {
+                1 => Vec::from(*b"HELD"), 2 => Vec::from(*b"HELD\x00BLK"), _ => Vec::from(*b"HELD\x00BLOCK")
+            }]), (1,1), false),
+            RenderableObject::new([held_block.obj.pos[0], held_block.obj.lines(piece_set.grid as isize)+3], VecDeque::from(vec![Vec::new()]), (1,1), false),
+            RenderableObject::new([held_block.obj.pos[0], held_block.obj.lines(piece_set.grid as isize)+4], VecDeque::from(vec![Vec::new()]), (1,1), false),
+        ];
+
+        let held_lines = held_block.obj.lines(piece_set.grid as isize);
+        let panel = crate::hud::panel([held_block.obj.pos[0], held_lines+6]);
+        let stats = args.stats.then(|| crate::stats::panel(piece_set.pieces.len(), [held_block.obj.pos[0] + crate::hud::WIDTH as isize + 2, held_lines+3], playfield.lines(PF_HEIGHT)));
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut queue: VecDeque<Block> = (0..=QUEUE_LEN).map(|_| Block::new_random(&defs, &mut rng)).collect();
+        Self {
+            playfield,
+            current_block: queue.pop_front().unwrap(),
+            queue,
+            held_block,
+            has_held: false,
+            hud,
+            panel,
+            stats,
+            finesse: (args.finesse || args.strict_finesse).then(|| Finesse::new(args.strict_finesse)),
+            animations: (!args.no_animations).then(|| Animations::new(Duration::from_millis(args.line_clear_delay))),
+            controls,
+            defs,
+            drawn: 0,
+            lines: 0,
+            score: 0,
+            combo: 0,
+            back_to_back: 0,
+            placed: 0,
+            attack: 0,
+            keys: 0,
+            histogram: vec![0; piece_set.pieces.len()],
+            rng,
+            garbage_rng: StdRng::seed_from_u64(!seed),
+            attack_table: args.attack_table.clone(),
+            incoming: Arc::new(AtomicUsize::new(0)),
+            target: None,
+            is_bot: false,
+            seed,
+            events: Vec::new(),
+            start: Instant::now(),
+            played: Duration::ZERO,
+            clock: None,
+            redraw: Arc::clone(redraw),
+        }
+    }
+
+    fn next_block(&mut self) -> Block {
+        self.drawn += 1;
+        self.queue.push_back(Block::new_random(&self.defs, &mut self.rng));
+        self.queue.pop_front().unwrap()
+    }
+
+    // x coordinate right after the held block, the HUD & the stats panel
+    pub fn right_edge(&self) -> isize {
+        let hold = &self.held_block.obj;
+        let stats = self.stats.as_ref().map_or(0, |stats| stats.pos[0] + stats.shape[0].len() as isize);
+        (hold.pos[0] + hold.shape[0].len() as isize*hold.scale.0 + 2).max(self.panel.pos[0] + crate::hud::WIDTH as isize).max(stats)
+    }
+
+    // y coordinate right under the playfield, the HUD & the stats panel
+    pub fn bottom(&self) -> isize {
+        let under = |obj: &RenderableObject| obj.pos[1] + obj.shape.len() as isize;
+        (self.playfield.lines(PF_HEIGHT) + 3).max(under(&self.panel)).max(self.stats.as_ref().map_or(0, under))
+    }
+
+    // Handle a key press, returns None if it isn't one of this player's controls, otherwise whether the player topped out
+    pub fn handle_key(&mut self, key: KeyCode) -> Option<bool> {
+        if self.is_bot {return None}
+        let controls = &self.controls;
+
+        let action = if key == controls.left {Action::Left}
+            else if key == controls.right {Action::Right}
+            else if key == controls.rotate_left {Action::RotateLeft}
+            else if key == controls.rotate_right {Action::RotateRight}
+            else if key == controls.hold {Action::Hold}
+            else if key == controls.soft_drop {Action::SoftDrop}
+            else if key == controls.hard_drop {Action::HardDrop}
+            else {return None};
+
+        self.keys += 1;
+        Some(self.act(action))
+    }
+
+    // Record & apply an action, returns whether the player topped out
+    pub fn act(&mut self, action: Action) -> bool {
+        self.record(action);
+        self.apply(action)
+    }
+    fn record(&mut self, action: Action) {
+        let at = self.time().as_millis() as u64;
+        log::log!(if matches!(action, Action::Gravity) {log::Level::Trace} else {log::Level::Debug}, "player {:x} at {}ms: {:?}", self.seed, at, action); //gravity is too much for debugging moves
+        self.events.push((at, action));
+    }
+    pub fn time(&self) -> Duration {self.clock.unwrap_or_else(|| self.played + self.start.elapsed())}
+    pub fn set_time(&mut self, time: Duration) {self.clock = Some(time);}
+    pub fn resume(&mut self, played: Duration) {(self.start, self.played, self.clock) = (Instant::now(), played, None);} //resumed games keep counting from where they stopped
+    pub fn pause(&mut self, by: Duration) {self.start += by;} //the clock doesn't count time spent suspended
+    pub fn freeze_clock(&mut self) {self.clock = Some(self.time());} //stays on the same time until thawed
+    pub fn thaw_clock(&mut self) {self.clock = None;} //pause() by however long it was frozen first
+
+    // Apply an action without recording it, used for playing replays back
+    pub fn apply(&mut self, action: Action) -> bool {
+        self.redraw.request(); //drawn as soon as the lock is released
+        self.finish_clear();
+        let now = self.time();
+        if let Some(finesse) = &mut self.finesse {finesse.input(action, now);}
+
+        let playfield = &self.playfield;
+        match action {
+            Action::Left => _=self.current_block.mov(-1, 0, playfield),
+            Action::Right => _=self.current_block.mov(1, 0, playfield),
+            Action::RotateLeft => _=self.current_block.rotate(-1, playfield),
+            Action::RotateRight => _=self.current_block.rotate(1, playfield),
+            Action::Hold => self.hold(),
+            Action::SoftDrop => _=self.current_block.mov(0, 1, playfield),
+            Action::HardDrop => {
+                while self.current_block.mov(0, 1, &self.playfield) == CollisionResult::NoCollision {}
+                return self.lock()
+            }
+            Action::Gravity => return self.current_block.mov(0, 1, playfield) != CollisionResult::NoCollision && self.lock(),
+            Action::Place {rotation, x, y} => { //replays & saves get shared, so the position might be anywhere
+                let mut placed = self.current_block.clone();
+                placed.obj.shape = VecDeque::from(placed.piece.rotations[rotation % 4].clone());
+                placed.rotation = rotation % 4;
+                placed.obj.pos = [x, y];
+                let near = x.unsigned_abs() <= 2*PF_WIDTH && y.unsigned_abs() <= 2*PF_HEIGHT as usize; //anywhere else can't be on the playfield & might overflow
+                if near && placed.obj.check_collision(playfield) == CollisionResult::NoCollision {self.current_block = placed;}
+                else {log::warn!("player {:x} ignored placing {} at {:?}, it doesn't fit there", self.seed, placed.piece.name, [x, y]);}
+            }
+            Action::Garbage {lines} => return self.playfield.add_garbage(lines, self.garbage_rng.gen_range(0..PF_WIDTH)),
+            Action::Restart => { //empty the playfield & forget about incoming garbage, keeps the pieces going
+                for row in self.playfield.shape.iter_mut() {row.fill(0);}
+                self.incoming.store(0, Ordering::SeqCst);
+                (self.combo, self.back_to_back) = (0, 0);
+            }
+        }
+        false
+    }
+
+    fn hold(&mut self) {
+        let next = if self.has_held {self.defs[self.held_block.id].clone()} else {self.next_block()};
+        self.has_held = true;
+
+        let hold_pos = self.held_block.obj.pos; //held blocks go back to their spawn orientation
+        self.held_block = self.defs[self.current_block.id].clone();
+        self.held_block.obj.pos = hold_pos;
+        self.held_block.obj.is_bordered = true;
+
+        self.current_block = next;
+        if let Some(finesse) = &mut self.finesse {finesse.reset();}
+    }
+    pub fn held_piece(&self) -> Option<&Arc<Piece>> {self.has_held.then_some(&self.held_block.piece)}
+    pub fn swapped_in_by_hold(&self) -> &Block {if self.has_held {&self.defs[self.held_block.id]} else {&self.queue[0]}}
+
+    // Move the current block straight to where its cells cover exactly the given [x, y] playfield cells, returns whether it fit
+    pub fn place(&mut self, rotation: usize, cells: &[[isize; 2]]) -> bool {
+        let cblock = &mut self.current_block;
+        let (old_shape, old_pos, old_rotation) = (cblock.obj.shape.clone(), cblock.obj.pos, cblock.rotation);
+        cblock.obj.shape = VecDeque::from(cblock.piece.rotations[rotation].clone());
+        cblock.rotation = rotation;
+
+        let cells_of = |obj: &RenderableObject| {
+            let mut cells: Vec<[isize; 2]> = obj.cells().into_iter().map(|(cell, _)| cell).collect();
+            cells.sort_unstable();
+            cells
+        };
+        let mut wanted = cells.to_vec();
+        wanted.sort_unstable();
+
+        if let (Some(from), Some(to)) = (cells_of(&cblock.obj).first(), wanted.first()) { //line up the first cells
+            cblock.obj.pos[0] += to[0]-from[0];
+            cblock.obj.pos[1] += to[1]-from[1];
+        }
+        if cells_of(&cblock.obj) == wanted && cblock.obj.check_collision(&self.playfield) == CollisionResult::NoCollision {
+            let (rotation, [x, y]) = (cblock.rotation, cblock.obj.pos);
+            self.record(Action::Place {rotation, x, y});
+            return true
+        }
+
+        cblock.obj.shape = old_shape;
+        cblock.obj.pos = old_pos;
+        cblock.rotation = old_rotation;
+        false
+    }
+
+    // Nobody tops out while cleared rows are still in the way
+    pub fn topped_out(&self) -> bool {
+        !self.rows_pending() && self.current_block.obj.check_collision(&self.playfield) == CollisionResult::GameOver
+    }
+
+    // Whether the current block sits on something & would lock on the next gravity tick
+    pub fn resting(&self) -> bool {
+        let mut below = self.current_block.obj.clone();
+        below.pos[1] += 1;
+        below.check_collision(&self.playfield) != CollisionResult::NoCollision
+    }
+
+    // Move the current block down, returns whether the player topped out
+    pub fn gravity(&mut self) -> bool {
+        if self.clearing() {return false} //pieces wait for cleared rows to collapse
+        if self.topped_out() {return true}
+        if self.is_bot {return false} //bots place their pieces themselves
+
+        self.act(Action::Gravity)
+    }
+
+    // Permanently draw the current block onto the playfield & spawn a new one, returns whether the player topped out
+    fn lock(&mut self) -> bool {
+        if self.check_finesse() {return false}
+        log::debug!("player {:x} locked {} at {:?}", self.seed, self.current_block.piece.name, self.current_block.obj.pos);
+        self.current_block.obj.imprint_to(&mut self.playfield);
+        self.placed += 1;
+        self.histogram[self.current_block.id] += 1;
+        self.flash_lock(self.current_block.obj.cells().into_iter().map(|(cell, _)| cell).collect());
+        self.current_block = self.next_block();
+
+        let rows = self.playfield.filled_lines();
+        let lines = rows.len();
+        self.clear_rows(rows);
+        match lines {
+            0 => self.combo = 0,
+            _ => {
+                self.combo += 1;
+                self.back_to_back = if lines >= 4 {self.back_to_back + 1} else {0};
+            }
+        }
+        self.lines += lines;
+        self.score += SCORES.get(lines).or(SCORES.last()).copied().unwrap_or(0);
+        let mut attack = match lines {
+            0 => 0,
+            _ => self.attack_table.get(lines-1).or(self.attack_table.last()).copied().unwrap_or(0),
+        };
+        self.attack += attack;
+
+        let incoming = self.incoming.load(Ordering::SeqCst);
+        let cancelled = attack.min(incoming); //attacks cancel incoming garbage first
+        attack -= cancelled;
+        self.incoming.fetch_sub(cancelled, Ordering::SeqCst);
+        if lines > 0 {log::debug!("player {:x} cleared {} lines: score {}, combo {}, b2b {}, {} attack & {} cancelled", self.seed, lines, self.score, self.combo, self.back_to_back, attack, cancelled);}
+
+        if attack > 0 {
+            match &self.target {
+                Some(Target::Local(incoming)) => _=incoming.fetch_add(attack, Ordering::SeqCst),
+                Some(Target::Remote(link)) => _=link.lock().unwrap().send_garbage(attack),
+                None => (),
+            }
+        }
+
+        if lines == 0 { //garbage only rises if no lines were cleared
+            let garbage = self.incoming.swap(0, Ordering::SeqCst);
+            if garbage > 0 {
+                self.record(Action::Garbage {lines: garbage});
+                return self.apply(Action::Garbage {lines: garbage})
+            }
+        }
+        false
+    }
+
+    pub fn held(&self) -> Vec<Vec<u8>> {self.held_block.obj.shape.clone().into()}
+
+    // Playfield with the current block drawn onto it
+    pub fn board(&self) -> Vec<Vec<u8>> {
+        let mut playfield = self.playfield.clone();
+        self.current_block.obj.imprint_to(&mut playfield);
+        playfield.shape.into()
+    }
+
+    pub fn render(&mut self, frame: &mut Frame, ghost: bool) {
+        let clearing = self.clearing(); //the next piece shows up once the rows are gone
+        match self.animated_playfield() {
+            Some(playfield) => _=playfield.render(frame),
+            None => _=self.playfield.render(frame),
+        }
+
+        if ghost && !clearing {
+            let mut ghost = self.current_block.clone();
+            while ghost.mov(0, 1, &self.playfield) == CollisionResult::NoCollision {}
+
+            for row in ghost.obj.shape.iter_mut() {
+                for col in row {
+                    if *col != 0 {*col = 2;}
+                }
+            }
+            ghost.obj.render_on(&self.playfield, frame);
+        }
+
+        if !clearing {self.current_block.obj.render_on(&self.playfield, frame);}
+        self.held_block.obj.render(frame);
+
+        if self.target.is_some() {self.hud[1].shape[0] = format!("GARBAGE {:<2}", self.incoming.load(Ordering::SeqCst)).bytes().collect();}
+        if let Some(finesse) = &self.finesse {self.hud[2].shape[0] = format!("FAULTS {:<3}", finesse.faults).bytes().collect();}
+        self.update_hud();
+        for obj in self.hud.iter().chain([&self.panel]).chain(&self.stats) {obj.render(frame);}
+    }
+}
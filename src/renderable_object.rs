@@ -1,7 +1,21 @@
-use std::{collections::VecDeque, io::Write};
-use crossterm::{cursor, execute, style::{SetForegroundColor, Color}};
-
-use crate::CollisionResult;
+use std::{collections::VecDeque, sync::Arc};
+use crossterm::style::Color;
+
+use crate::{frame::Frame, CollisionResult};
+
+pub const PIECE_CELL: u8 = 3; //cells from here up to 0x1F belong to pieces and are coloured using the palette
+pub fn is_solid(cell: u8) -> bool {cell == 1 || (PIECE_CELL..0x20).contains(&cell)}
+
+// Part of a scaled cell in ASCII, e.g. [] or [#] for pieces & garbage, .. for ghosts
+fn ascii_cell(cell: u8, x: isize, width: isize) -> char {
+    match (cell, x) {
+        (2, _) => '.',
+        _ if width == 1 => '#',
+        (_, 0) => '[',
+        _ if x == width-1 => ']',
+        _ => '#',
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct RenderableObject {
@@ -9,108 +23,149 @@ pub struct RenderableObject {
     pub shape: VecDeque<Vec<u8>>,
     pub scale: (isize, isize),
     pub is_bordered: bool,
+    pub half: bool, //two rows per line using half blocks, scale.1 counts half lines then
+    pub palette: Arc<[Color]>,
+    pub highlighted: bool, //text in the theme's highlight colour
 }
 impl RenderableObject {
-    pub fn new(pos: [isize; 2], shape: VecDeque<Vec<u8>>, scale: (isize, isize), is_bordered: bool) -> Self {Self {pos, shape, scale, is_bordered}}
+    pub fn new(pos: [isize; 2], shape: VecDeque<Vec<u8>>, scale: (isize, isize), is_bordered: bool) -> Self {Self {pos, shape, scale, is_bordered, half: false, palette: Arc::from([]), highlighted: false}}
+
+// Lines on the screen the given number of rows take up, without borders
+    pub fn lines(&self, rows: isize) -> isize {if self.half {(rows*self.scale.1 + 1)/2} else {rows*self.scale.1}}
+// Blocks are positioned in board cells of the playfield they're on, scale only matters once they get drawn
     pub fn check_collision(&self, other: &Self) -> CollisionResult {
-        for (y, row) in self.shape.iter().enumerate() {
-            for (x, &col) in row.iter().enumerate() {
-                if col == 1 {
-                    let actual_x = self.scale.0*x as isize + self.pos[0];
-                    let actual_y = self.scale.1*y as isize + self.pos[1];
-
-                // Wall boundary check
-                    if actual_y <= other.is_bordered as isize+other.pos[1] || actual_y >= crate::PF_HEIGHT+2*other.is_bordered as isize
-                    || actual_x < other.pos[0] || actual_x > other.pos[0] + other.scale.0*crate::PF_WIDTH as isize
-                    {return CollisionResult::OutOfBounds}
-
-                // Collision detection
-                    if other.shape
-                        [((self.scale.1*y as isize + self.pos[1] - other.pos[1])/other.scale.1 as isize - 2*other.is_bordered as isize) as usize]
-                        [((self.scale.0*x as isize + self.pos[0] - other.pos[0])/other.scale.0 as isize) as usize] == 1
-                    {return if self.pos[1] == 2 {CollisionResult::GameOver} else {CollisionResult::BlockCollision}}
-                }
-            }
+        for ([x, y], _) in self.cells() {
+        // Wall boundary check
+            if x < 0 || y < 0 || x >= other.shape[0].len() as isize || y >= other.shape.len() as isize {return CollisionResult::OutOfBounds}
+
+        // Collision detection
+            if is_solid(other.shape[y as usize][x as usize]) {return if self.pos[1] == 0 {CollisionResult::GameOver} else {CollisionResult::BlockCollision}}
         }
         CollisionResult::NoCollision
     }
 
-// Check for & remove filled lines
-    pub fn check_line_fills(&mut self) -> &mut Self{
-        let len = self.shape.len(); //cache original length
-        let mut idxs = Vec::new();
+// Check for & remove filled lines, returns how many were removed
+    pub fn check_line_fills(&mut self) -> usize {
+        let idxs = self.filled_lines();
+        self.remove_lines(&idxs);
+        idxs.len()
+    }
 
-    // Find indexes of filled lines
+// Indexes of filled lines, top to bottom
+    pub fn filled_lines(&self) -> Vec<usize> {
+        let mut idxs = Vec::new();
         for (i, row) in self.shape.iter().enumerate() {
             let mut filled = true;
             for &col in row.iter() {
-                if col != 1 {filled = false; break}
+                if !is_solid(col) {filled = false; break}
             }
             if filled {idxs.push(i);}
         }
+        idxs
+    }
 
-    // Remove filled lines
-        idxs.sort_unstable(); //sort and rev() the iterator to avoid removing incorrect lines
-        for i in idxs.into_iter().rev() {_=self.shape.remove(i);}
-        for _ in 0..len-self.shape.len() {self.shape.push_front(vec![0u8; self.shape[0].len()])} //replace removed lines
+    pub fn remove_lines(&mut self, idxs: &[usize]) {
+        for &i in idxs.iter().rev() {_=self.shape.remove(i);} //bottom to top to avoid removing incorrect lines
+        for _ in idxs {self.shape.push_front(vec![0u8; self.shape[0].len()])} //replace removed lines
+    }
 
-        self
+// Push everything up & add garbage lines with a hole at the given column, returns whether any blocks got pushed out
+    pub fn add_garbage(&mut self, lines: usize, hole: usize) -> bool {
+        let width = self.shape[0].len();
+        let mut topped_out = false;
+
+        for _ in 0..lines.min(self.shape.len()) {
+            topped_out |= self.shape.pop_front().unwrap().into_iter().any(is_solid);
+
+            let mut row = vec![1u8; width];
+            row[hole] = 0;
+            self.shape.push_back(row);
+        }
+
+        topped_out
     }
 
     pub fn imprint_to(&self, other: &mut Self) -> &Self {
+        for ([x, y], col) in self.cells() {
+            other.shape[y as usize][x as usize] = col;
+        }
+
+        self
+    }
+
+// Solid cells as [x, y] board coordinates, which might be out of the playfield's bounds
+    pub fn cells(&self) -> Vec<([isize; 2], u8)> {
+        let mut cells = Vec::new();
         for (y, row) in self.shape.iter().enumerate() {
             for (x, &col) in row.iter().enumerate() {
-                if col == 1 {
-                    other.shape
-                        [((self.scale.1*y as isize + self.pos[1] - other.pos[1])/other.scale.1 as isize - 2) as usize]
-                        [((self.scale.0*x as isize + self.pos[0] - other.pos[0])/other.scale.0 as isize) as usize]
-                    = 1;
-                }
+                if is_solid(col) {cells.push(([self.pos[0] + x as isize, self.pos[1] + y as isize], col));}
             }
         }
+        cells
+    }
 
-        self
+// Main rendering function, empty cells are see-through
+    pub fn render(&self, frame: &mut Frame) -> &Self {
+        self.draw(frame, [self.pos[0], (self.pos[1]-1).max(0)], 0, self) //y=0 & y=1 both end up on the top row
     }
 
-// Main rendering function
-    pub fn render<W: Write>(self: &Self, buf: &mut W) -> &Self {
-        let hborder = "─".repeat(self.shape[0].len()*self.scale.0 as usize);
-        let move_to_start = cursor::MoveToColumn((self.pos[0]) as u16);
-
-        execute!(buf, move_to_start, cursor::MoveDown((self.pos[1]) as u16)).unwrap(); //move cursor to pos
-
-        if self.is_bordered {write!(buf, "┌{}┐\n", &hborder).unwrap();} //draw upper border
-        execute!(buf, move_to_start).unwrap();
-
-        for row in self.shape.iter() {
-            for _ in 0..self.scale.1 {
-                if self.is_bordered {buf.write_all(&[0xE2, 0x94, 0x82]).unwrap();} //unicode encoding of │ - left border
-                for &col in row.iter() {
-                    if col==1 { //white
-                        for _ in 0..self.scale.0 {
-                            buf.write_all(&[0xE2, 0x96, 0x88]).unwrap(); //unicode of █
-                        }
-                    } else if col==2 { //gray
-                        execute!(buf, SetForegroundColor(Color::DarkGrey)).unwrap();
-                        for _ in 0..self.scale.0 {
-                            buf.write_all(&[0xE2, 0x96, 0x88]).unwrap(); //unicode of █
-                        }
-                        execute!(buf, SetForegroundColor(Color::Reset)).unwrap();
-                    } else if col >= 0x20 { //ASCII
-                        for _ in 0..self.scale.0 {
-                            write!(buf, "{}", col as char).unwrap();
-                        }
-                    } else {execute!(buf, cursor::MoveRight(self.scale.0 as u16)).unwrap();}
+// Render a block at its board position inside the playfield, looking like the playfield
+    pub fn render_on(&self, playfield: &Self, frame: &mut Frame) -> &Self {
+        let origin = [playfield.pos[0] + 1 + self.pos[0]*playfield.scale.0, (playfield.pos[1]-1).max(0) + 1];
+        self.draw(frame, origin, self.pos[1]*playfield.scale.1, playfield)
+    }
+
+// Draw with the top left corner at the given frame cell, starting sub lines (or half lines) further down, scaled like look
+    fn draw(&self, frame: &mut Frame, [x0, y0]: [isize; 2], sub: isize, look: &Self) -> &Self {
+        let (scale, half) = (look.scale, look.half);
+        let border = self.is_bordered as isize;
+        let width = self.shape[0].len() as isize*scale.0;
+        let ([top_left, top_right, bottom_left, bottom_right], horizontal, vertical) = if frame.ascii {(['+'; 4], '-', '|')} else {(['┌', '┐', '└', '┘'], '─', '│')};
+        let theme = Arc::clone(&frame.theme);
+        let (border_colour, text) = (theme.colour(theme.border), theme.colour(if self.highlighted {theme.highlight} else {theme.text}));
+        let bg = theme.background.filter(|_| look.is_bordered).map_or(Color::Reset, |bg| theme.colour(bg)); //blocks on a playfield get its background
+
+        if self.is_bordered { //upper & lower border, background in between
+            let bottom = y0 + look.lines(self.shape.len() as isize) + 1;
+            for (y, [left, right]) in [(y0, [top_left, top_right]), (bottom, [bottom_left, bottom_right])] {
+                frame.put(x0, y, left, border_colour, Color::Reset);
+                for x in 0..width {frame.put(x0+1+x, y, horizontal, border_colour, Color::Reset);}
+                frame.put(x0+1+width, y, right, border_colour, Color::Reset);
+            }
+            if bg != Color::Reset {
+                for y in y0+1..bottom {
+                    for x in 0..width {frame.put(x0+1+x, y, ' ', Color::Reset, bg);}
                 }
-                if self.is_bordered {buf.write_all(&[0xE2, 0x94, 0x82]).unwrap();} // │ - right border
-                execute!(buf, move_to_start, /*cursor::MoveDown(1)*/crossterm::style::Print("\n")).unwrap();
             }
         }
 
-        if self.is_bordered {write!(buf, "└{}┘", &hborder).unwrap();} //draw lower border
-    
-        execute!(buf, cursor::MoveToColumn(0), cursor::MoveUp((self.pos[1]+self.shape.len() as isize) as u16 + (self.is_bordered as u16)*2)).unwrap(); //reset cursor
+        for (row_y, row) in self.shape.iter().enumerate() {
+            for sy in 0..scale.1 {
+                let line = sub + row_y as isize*scale.1 + sy;
+                let y = y0 + border + if half {line/2} else {line};
+                if self.is_bordered { //left & right border
+                    frame.put(x0, y, vertical, border_colour, Color::Reset);
+                    frame.put(x0+1+width, y, vertical, border_colour, Color::Reset);
+                }
+
+                for (col_x, &col) in row.iter().enumerate() {
+                    let (ch, fg) = match col {
+                        1 => (theme.cell, theme.colour(theme.garbage)),
+                        2 => (theme.ghost_cell, theme.colour(theme.ghost)),
+                        c if is_solid(c) => (theme.cell, theme.colour(theme.piece((c-PIECE_CELL) as usize, &self.palette))),
+                        c if c >= 0x20 => (c as char, text), //ASCII
+                        _ => continue,
+                    };
+                    for sx in 0..scale.0 {
+                        let x = x0 + border + col_x as isize*scale.0 + sx;
+                        if half && col < 0x20 {frame.put_half(x, y, line%2 == 1, if fg == Color::Reset {Color::White} else {fg})} //no background colour for the default one
+                        else if frame.ascii && col < 0x20 {frame.put(x, y, ascii_cell(col, sx, scale.0), fg, bg)}
+                        else {frame.put(x, y, ch, fg, bg)}
+                    }
+                }
+            }
+        }
 
         self
     }
-}
\ No newline at end of file
+}
@@ -1,78 +1,250 @@
-use std::{thread, sync::{Arc, Mutex}, io::{self, Write}, time::Duration, collections::VecDeque};
-use crossterm::{execute, cursor, terminal::{Clear, ClearType}};
-
-use crate::renderable_object::*;
-
-
-pub fn thread(
-    args: Arc<crate::Args>,
-    bomb: bombs::Bomb<()>,
-    objects: Arc<Mutex<Vec<RenderableObject>>>,
-    current_block: Arc<Mutex<crate::Block>>,
-    held_block: Arc<Mutex<crate::Block>>,
-) -> thread::JoinHandle<()> {
-    thread::spawn(move || {
-    // HELD text object
-        let held = {
-            let hblock = held_block.lock().unwrap();
-            RenderableObject::new([hblock.obj.pos[0]+1, hblock.obj.pos[1]], VecDeque::from(vec![match args.width_scale {
-                1 => Vec::from(*b"HELD"), 2 => Vec::from(*b"HELD\x00BLK"), _ => Vec::from(*b"HELD\x00BLOCK")
-            }]), (1,1), false)
-        };
-
-    // Main loop
-        while bomb.exploded().is_none() { //check for close signal
-        // The actual rendering
-            execute!(io::stdout().lock(), cursor::MoveUp(1)).unwrap();
-            {
-                let mut stdoutl = io::stdout().lock();
-                let cblock = &mut current_block.lock().unwrap();
-                
-            // Debug text
-                if args.debug {
-                    objects.lock().unwrap()[1].shape[0] = cblock.obj.pos[0].to_string().bytes().collect();
-                    objects.lock().unwrap()[1].shape[1] = cblock.obj.pos[1].to_string().bytes().collect();
-                }
+use std::{sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, io::{self, Write}, time::{Duration, Instant}, collections::VecDeque};
+use bombs::Fuse;
 
-            // Call rendering functions
-                for obj in objects.lock().unwrap().iter() { //playfield && text
-                    obj.render(&mut stdoutl);
-                }
-                
-                // Ghost piece
-                if !args.disable_ghost {
-                    let old_pos = cblock.obj.pos;
-                    let old_shape = cblock.obj.shape.clone();
-                    
-                    while cblock.mov(0, 1, &objects.lock().unwrap()[0]) == crate::CollisionResult::NoCollision {}
-
-                    for row in cblock.obj.shape.iter_mut() {
-                        for col in row {
-                            if *col != 0 {*col = 2;}
-                        }
-                    }
-
-                    cblock.obj.render(&mut stdoutl);
-                    cblock.obj.pos = old_pos;
-                    cblock.obj.shape = old_shape;
-                }
+use crate::{theme::Theme, renderable_object::*, animation::{grey_out, GREY_OUT}, frame::{Frame, Screen}, player::Player, PF_HEIGHT, PF_WIDTH};
 
-                cblock.obj.render(&mut stdoutl);
-                held_block.lock().unwrap().obj.render(&mut stdoutl);
-                held.render(&mut stdoutl);
+// Requests for the game loop from other threads
+#[derive(Debug, Default)]
+pub struct Redraw {
+    pending: AtomicBool,
+    ended: Mutex<Option<Vec<[u8; 12]>>>, //titles for the end screen once the game's over
+}
+impl Redraw {
+    pub fn request(&self) {self.pending.store(true, Ordering::SeqCst)}
 
-                _=stdoutl.flush();
-            }
+    pub fn ended(&self) -> Option<Vec<[u8; 12]>> {self.ended.lock().unwrap().take()}
+}
+
+// Writing to the terminal failed, there's no point in going on then
+#[derive(Debug)]
+pub struct RenderError(io::Error);
+impl From<io::Error> for RenderError {
+    fn from(e: io::Error) -> Self {Self(e)}
+}
+impl std::fmt::Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {write!(f, "couldn't draw to the terminal: {}", self.0)}
+}
+impl std::error::Error for RenderError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {Some(&self.0)}
+}
+
+// Draws whenever something changed, but no more often than the framerate
+pub struct Renderer {
+    screen: Screen,
+    frame: Duration,
+    last: Option<Instant>, //when the last frame was drawn
+    clock: Option<u64>, //seconds on the clock in the last frame
+    titles: Option<Vec<[u8; 12]>>, //banners on top of the playfields once the game's over
+    greying: Option<Instant>, //when the game over animation started, until it's done
+    theme: Arc<Theme>,
+    redraw: Arc<Redraw>,
+}
+impl Renderer {
+    pub fn new(args: &crate::Args, theme: Arc<Theme>, redraw: Arc<Redraw>) -> Self {
+        Self {screen: Screen::new(args.fullscreen), frame: Duration::from_nanos(1_000_000_000/args.framerate as u64), last: None, clock: None, titles: None, greying: None, theme, redraw}
+    }
+
+    pub fn forget(&mut self) {
+        self.screen.forget();
+        self.redraw.request();
+    }
+
+    // When the game loop should come back at the latest
+    pub fn next(&self, now: Instant) -> Instant {
+        match self.last {
+            Some(last) if self.redraw.pending.load(Ordering::SeqCst) => (last + self.frame).max(now),
+            _ => now + self.frame,
+        }
+    }
+
+    // Draw a frame if anything changed, animations change every frame & clocks every second
+    pub fn update(&mut self, args: &crate::Args, players: &[Mutex<Player>], objects: &Mutex<Vec<RenderableObject>>, now: Instant) -> Result<(), RenderError> {
+        if self.last.is_some_and(|last| now < last + self.frame) {return Ok(())}
+        let clock = players.first().map(|player| player.lock().unwrap().time().as_secs());
+        let animating = players.iter().any(|player| player.lock().unwrap().animating());
+        if !self.redraw.pending.swap(false, Ordering::SeqCst) && !animating && self.greying.is_none() && clock == self.clock && self.last.is_some() {return Ok(())}
+        self.clock = clock;
+
+        if let Some(start) = self.greying {
+            let progress = start.elapsed().as_secs_f64()/GREY_OUT.as_secs_f64();
+            grey_out(players, progress.min(1.));
+            if progress >= 1. {self.greying = None;}
+        }
+
+        let mut frame = compose(args, &self.theme, players, objects);
+        if let Some(titles) = self.titles.as_ref().filter(|_| self.greying.is_none()) {
+            for (player, title) in players.iter().zip(titles) {banner(&player.lock().unwrap().playfield, title, b"Esc to exit.").render(&mut frame);}
+        }
+        self.show(frame)
+    }
+
+    // The actual rendering, in one write
+    fn show(&mut self, frame: Frame) -> Result<(), RenderError> {
+        let start = Instant::now();
+        let mut buf = Vec::new();
+        self.screen.show(frame, &mut buf)?;
+        let mut stdoutl = io::stdout().lock();
+        stdoutl.write_all(&buf)?;
+        stdoutl.flush()?;
+        log::trace!("drew {} bytes in {:?}, {:?} after the last frame", buf.len(), start.elapsed(), self.last.map_or(Duration::ZERO, |last| start - last));
+        self.last = Some(Instant::now());
+        Ok(())
+    }
+
+    // Stop the clocks & put the results under the playfields, the stacks grey out over the next frames & then get a message on top of each
+    pub fn end(&mut self, args: &crate::Args, players: &[Mutex<Player>], objects: &Mutex<Vec<RenderableObject>>, titles: Vec<[u8; 12]>) {
+        for player in players {
+            let player = &mut player.lock().unwrap();
+            let time = player.time();
+            player.set_time(time);
+        }
+        if let Some(y) = players.iter().map(|player| player.lock().unwrap().bottom()).max() {
+            let summary = crate::finesse::summary(players, [3, y]);
+            let table = crate::highscores::submit(args, players, [3, y + summary.as_ref().map_or(0, |summary| summary.shape.len() as isize+1)]);
+            objects.lock().unwrap().extend(summary.into_iter().chain(table));
+        }
+
+        if args.no_animations {grey_out(players, 1.);}
+        else {self.greying = Some(Instant::now());}
+        self.titles = Some(titles);
+        self.redraw.request();
+    }
 
-        // Wait between frames
-            thread::sleep(Duration::from_nanos(1_000_000_000/args.framerate as u64));
+    // Any key skips the rest of the game over animation
+    pub fn skip(&mut self, players: &[Mutex<Player>]) {
+        if self.greying.take().is_some() {
+            grey_out(players, 1.);
+            self.redraw.request();
+        }
+    }
+}
+
+// Everything there is to see
+fn compose(args: &crate::Args, theme: &Arc<Theme>, players: &[Mutex<Player>], objects: &Mutex<Vec<RenderableObject>>) -> Frame {
+    let mut frame = Frame::new(args.charset.unwrap_or_else(crate::frame::Charset::detect), theme);
+    for player in players.iter() { //playfields, blocks, held blocks & stats
+        let player = &mut player.lock().unwrap();
+        if args.stats {player.update_stats();}
+        player.render(&mut frame, !args.disable_ghost);
+    }
+    for obj in objects.lock().unwrap().iter() { //text & opponent's playfield
+        obj.render(&mut frame);
+    }
+    frame
+}
+
+// Stop all other threads & leave the end screen to the game loop, unless someone else ended the game already
+pub fn game_over(redraw: &Redraw, fuse: &Mutex<Option<Fuse<()>>>, titles: &[&[u8; 12]]) {
+    if let Some(_fuse) = std::mem::take(&mut *fuse.lock().unwrap()) {
+        _=_fuse.light(());
+        log::info!("game over: {:?}", titles.iter().map(|title| String::from_utf8_lossy(*title).trim().to_string()).collect::<Vec<_>>());
+        *redraw.ended.lock().unwrap() = Some(titles.iter().map(|title| **title).collect());
+    }
+}
+
+// Message box in the middle of a playfield
+pub fn banner(playfield: &RenderableObject, title: &[u8; 12], footer: &[u8; 12]) -> RenderableObject {
+    RenderableObject::new([playfield.pos[0]+PF_WIDTH as isize/2*playfield.scale.0-6, playfield.lines(PF_HEIGHT)/2-2], VecDeque::from(vec![
+        Vec::from(*title),
+        Vec::from(*b"------------"),
+        Vec::from(*footer),
+    ]), (1,1), true)
+}
+
+// Game over caused by one of the players topping out, everyone else wins
+pub fn lost(players: &[Mutex<Player>], fuse: &Mutex<Option<Fuse<()>>>, loser: usize) {
+    log::info!("player {} topped out", loser+1);
+    let titles: Vec<&[u8; 12]> = (0..players.len()).map(|i| if i == loser {b" GAME  OVER "} else {b" YOU   WIN! "}).collect();
+    let redraw = Arc::clone(&players[loser].lock().unwrap().redraw);
+    game_over(&redraw, fuse, &titles);
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::sync::Arc;
+    use crate::{vt::Terminal, frame::Charset, player::Action, theme::{Theme, ColourDepth}};
 
-        // Clear screen
-            execute!(io::stdout().lock(), Clear(ClearType::FromCursorDown)).unwrap();
+    const SIZE: (u16, u16) = (120, 70); //big enough for everything at 3x3
+    const SCALES: [(u8, u8); 6] = [(1, 1), (2, 1), (3, 1), (1, 2), (2, 2), (3, 3)];
+
+    // Every scale in every charset, plus half blocks which only exist in unicode
+    fn cases() -> Vec<((u8, u8), Charset, bool)> {
+        let mut cases = Vec::new();
+        for scale in SCALES {
+            cases.push((scale, Charset::Unicode, false));
+            cases.push((scale, Charset::Ascii, false));
+            cases.push((scale, Charset::Unicode, true));
+        }
+        cases
+    }
+
+    fn args(scale: (u8, u8), charset: Charset, half: bool) -> crate::Args {
+        let (w, v) = (scale.0.to_string(), scale.1.to_string());
+        let mut args = crate::Args::parse_from(["tetris-rs", "-w", &w, "-v", &v, "--no-animations"].into_iter().chain(half.then_some("--half-blocks")));
+        args.charset = Some(charset);
+        args
+    }
+
+    // A few pieces on a stack with some garbage, something held & the current piece floating above its ghost
+    fn player(args: &crate::Args) -> Player {
+        let mut player = crate::player::test_player(args, 42);
+        for action in [Action::Left, Action::Left, Action::HardDrop, Action::RotateRight, Action::Right, Action::Right, Action::Right, Action::HardDrop, Action::Hold, Action::Garbage {lines: 2}, Action::Right, Action::SoftDrop] {
+            player.apply(action);
+        }
+        player
+    }
+
+    fn draw(args: &crate::Args, screen: &mut Screen, terminal: &mut Terminal, player: Player) -> Player {
+        let players = [Mutex::new(player)];
+        players[0].lock().unwrap().set_time(Duration::ZERO);
+        let mut buf = Vec::new();
+        let theme = Arc::new(Theme::load("default", ColourDepth::Truecolor).unwrap());
+        screen.show(compose(args, &theme, &players, &Mutex::new(Vec::new())), &mut buf).unwrap();
+        terminal.feed(&buf);
+        let [player] = players;
+        player.into_inner().unwrap()
+    }
+
+    fn name(scale: (u8, u8), charset: Charset, half: bool) -> String {
+        format!("{}x{}{}-{}", scale.0, scale.1, if half {"-half"} else {""}, if charset == Charset::Ascii {"ascii"} else {"unicode"})
+    }
+
+    // Compares against the files in snapshots/, run with UPDATE_SNAPSHOTS=1 to write them after changing how things look
+    #[test]
+    fn snapshots() {
+        let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+        let mut failed = Vec::new();
+        for (scale, charset, half) in cases() {
+            let args = args(scale, charset, half);
+            let mut terminal = Terminal::new(SIZE.0 as usize, SIZE.1 as usize);
+            draw(&args, &mut Screen::sized(false, SIZE), &mut terminal, player(&args));
+
+            let path = format!("{}/snapshots/{}.txt", env!("CARGO_MANIFEST_DIR"), name(scale, charset, half));
+            let snapshot = terminal.snapshot();
+            if update {std::fs::write(&path, &snapshot).unwrap()}
+            else if std::fs::read_to_string(&path).ok().as_ref() != Some(&snapshot) {failed.push(path);}
+        }
+        assert!(failed.is_empty(), "snapshots differ, run with UPDATE_SNAPSHOTS=1 & check the diff:\n{}", failed.join("\n"));
+    }
+
+    // Drawing only the differences has to end up with the same screen as drawing everything from scratch
+    #[test]
+    fn redraws_match() {
+        for (scale, charset, half) in cases() {
+            for fullscreen in [false, true] {
+                let args = args(scale, charset, half);
+                let (mut screen, mut terminal) = (Screen::sized(fullscreen, SIZE), Terminal::new(SIZE.0 as usize, SIZE.1 as usize));
+                let mut player = draw(&args, &mut screen, &mut terminal, player(&args));
+                for action in [Action::RotateLeft, Action::Left, Action::Left, Action::HardDrop, Action::Hold, Action::Right] {
+                    player.apply(action);
+                    player = draw(&args, &mut screen, &mut terminal, player);
+                }
+
+                let mut fresh = Terminal::new(SIZE.0 as usize, SIZE.1 as usize);
+                draw(&args, &mut Screen::sized(fullscreen, SIZE), &mut fresh, player);
+                assert!(terminal == fresh, "{} {}", name(scale, charset, half), if fullscreen {"fullscreen"} else {"inline"});
+            }
         }
-    
-    // Exit
-        //let mut stdoutl = io::stdout().lock();
-        //_=write!(stdoutl, "Exiting...\r\n"); _=stdoutl.flush();
-    })
-}
\ No newline at end of file
+    }
+}
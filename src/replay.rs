@@ -0,0 +1,194 @@
+use std::{fs, sync::{Arc, Mutex}, io::{self, BufWriter}, time::{Duration, Instant}};
+use crossterm::event::KeyCode;
+use serde::{Serialize, Deserialize};
+
+use crate::{renderable_object::*, rendering::Redraw, piece_set::PieceSet, player::{Action, Controls, Player}, VERSION};
+
+const SEEK_STEP: f64 = 5000.; //milliseconds skipped per key press
+const MAX_SPEED: f64 = 16.;
+
+// Replay file, everything needed to play a game again exactly the same way
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    version: String,
+    pub pieces: String, //piece set the game was played with, as the file's contents
+    pub strict_finesse: bool,
+    players: Vec<PlayerReplay>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PlayerReplay {
+    seed: u64,
+    events: Vec<(u64, Action)>, //milliseconds since the start & what happened
+}
+
+impl Replay {
+    pub fn record(args: &crate::Args, piece_set: &PieceSet, players: &[Mutex<Player>]) -> Self {
+        Self {
+            version: String::from(VERSION),
+            pieces: piece_set.source.clone(),
+            strict_finesse: args.strict_finesse,
+            players: players.iter().map(|player| {
+                let player = player.lock().unwrap();
+                PlayerReplay {seed: player.seed, events: player.events.clone()}
+            }).collect(),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        serde_json::to_writer(BufWriter::new(fs::File::create(path)?), self).map_err(io::Error::from)
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = fs::File::open(path).map_err(|e| format!("couldn't read replay '{}': {}", path, e))?;
+        let replay: Self = serde_json::from_reader(io::BufReader::new(file)).map_err(|e| format!("invalid replay '{}': {}", path, e))?;
+        if replay.version != VERSION {return Err(format!("replay was recorded with version {}, this is {}", replay.version, VERSION))}
+        if replay.players.is_empty() {return Err(String::from("replay doesn't contain any players"))}
+        Ok(replay)
+    }
+
+    pub fn seeds(&self) -> Vec<u64> {self.players.iter().map(|player| player.seed).collect()}
+
+    // Milliseconds until the last event
+    fn length(&self) -> u64 {self.players.iter().filter_map(|player| player.events.last()).map(|&(at, _)| at).max().unwrap_or(0)}
+}
+
+// Status text under the first playfield
+pub fn status(y: isize) -> RenderableObject {
+    RenderableObject::new([3, y], [Vec::new(), Vec::from(*b"Space: pause  Left/Right: seek  Up/Down: speed")].into(), (1,1), false)
+}
+
+fn clock(ms: f64) -> String {format!("{}:{:02}", ms as u64/60000, ms as u64/1000%60)}
+
+// Plays a replay back on the players, moved along by the game loop & controlled by the keyboard
+pub struct Playback {
+    replay: Replay,
+    piece_set: PieceSet,
+    length: f64,
+    time: f64,         //milliseconds into the replay
+    next: Vec<usize>,  //index of every player's next event
+    last: Instant,     //when the clock was last moved
+    paused: bool,
+    speed: f64,
+    seek: f64, //milliseconds to skip on the next update, negative to rewind
+}
+impl Playback {
+    pub fn new(replay: Replay, now: Instant) -> Self {
+        Self {
+            piece_set: PieceSet::parse(&replay.pieces).unwrap(), //already checked when loading
+            length: replay.length() as f64,
+            time: 0.,
+            next: vec![0; replay.players.len()],
+            last: now,
+            paused: false,
+            speed: 1.,
+            seek: 0.,
+            replay,
+        }
+    }
+
+    // Nothing plays while the process is stopped
+    pub fn delay(&mut self, by: Duration) {self.last += by;}
+
+    pub fn handle_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::Char(' ') => self.paused = !self.paused,
+            KeyCode::Left => self.seek -= SEEK_STEP,
+            KeyCode::Right => self.seek += SEEK_STEP,
+            KeyCode::Up => self.speed = (self.speed*2.).min(MAX_SPEED),
+            KeyCode::Down => self.speed = (self.speed/2.).max(1./MAX_SPEED),
+            _ => (),
+        }
+    }
+
+    // Move the clock by however long it's been & catch up with the recorded events
+    pub fn update(&mut self, args: &crate::Args, redraw: &Arc<Redraw>, players: &[Mutex<Player>], objects: &Mutex<Vec<RenderableObject>>, status_idx: usize, now: Instant) {
+        let mut target = self.time + self.seek;
+        if !self.paused {target += (now - self.last).as_secs_f64()*1000.*self.speed;}
+        target = target.clamp(0., self.length);
+        (self.seek, self.last) = (0., now);
+        if target >= self.length {self.paused = true;} //stay on the last frame
+        let previous = self.time;
+
+        if target < self.time { //rewinding means playing everything again from the start
+            for (player, seed) in players.iter().zip(self.replay.seeds()) {
+                let player = &mut player.lock().unwrap();
+                **player = Player::new(args, redraw, &self.piece_set, Controls::parse(&args.controls).unwrap(), player.playfield.pos[0], seed);
+            }
+            self.next.fill(0);
+        }
+        self.time = target;
+
+        for ((player, recorded), next) in players.iter().zip(&self.replay.players).zip(self.next.iter_mut()) {
+            let player = &mut player.lock().unwrap();
+            while let Some(&(at, action)) = recorded.events.get(*next) {
+                if at as f64 > self.time {break}
+                player.set_time(Duration::from_millis(at)); //at the time it happened, finesse tells taps from held keys by it
+                player.apply(action);
+                *next += 1;
+            }
+            player.set_time(Duration::from_millis(self.time as u64));
+        }
+
+        objects.lock().unwrap()[status_idx].shape[0] = format!("REPLAY {} / {}  x{}{}",
+            clock(self.time), clock(self.length), self.speed, if self.time >= self.length {"  END"} else if self.paused {"  PAUSED"} else {""}
+        ).bytes().collect();
+        if self.time != previous {redraw.request();}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    // Playing a recording back ends up with exactly the same game
+    #[test]
+    fn deterministic() {
+        let args = crate::Args::parse_from(["tetris-rs", "--split-screen", "--strict-finesse"]);
+        let piece_set = PieceSet::load("classic").unwrap();
+        let new = |seed| crate::player::test_player(&args, seed);
+
+        let players = [Mutex::new(new(3)), Mutex::new(new(4))];
+        for (i, player) in players.iter().enumerate() {
+            let player = &mut player.lock().unwrap();
+            for piece in 0..40u64 {
+                player.set_time(Duration::from_millis(piece*400));
+                match piece % 7 {
+                    0 => _=player.act(Action::Garbage {lines: 1 + i}),
+                    3 => _=player.act(Action::Hold),
+                    5 => for action in [Action::Left, Action::Right, Action::RotateRight, Action::RotateLeft, Action::HardDrop] {player.act(action);}, //a finesse fault, sent back up
+                    _ => (),
+                }
+                if crate::ai::play(player, &crate::ai::DEFAULT_WEIGHTS) {player.act(Action::Restart);}
+            }
+        }
+        let replay: Replay = serde_json::from_str(&serde_json::to_string(&Replay::record(&args, &piece_set, &players)).unwrap()).unwrap();
+
+        let start = Instant::now();
+        let mut playback = Playback::new(replay, start);
+        let played = [Mutex::new(new(3)), Mutex::new(new(4))];
+        playback.update(&args, &Default::default(), &played, &Mutex::new(vec![status(0)]), 0, start + Duration::from_secs(60));
+        for (player, played) in players.iter().zip(&played) {
+            let (player, played) = (&mut player.lock().unwrap(), &mut played.lock().unwrap());
+            player.finish_clear();
+            played.finish_clear();
+            assert_eq!((player.board(), player.score, player.drawn), (played.board(), played.score, played.drawn));
+            assert!(player.finesse.as_ref().unwrap().faults > 0);
+            assert_eq!(player.finesse.as_ref().unwrap().faults, played.finesse.as_ref().unwrap().faults);
+        }
+    }
+
+    // Replays get shared, a placement outside the playfield is ignored instead of crashing the viewer
+    #[test]
+    fn impossible_placements() {
+        let args = crate::Args::parse_from(["tetris-rs"]);
+        let mut player = crate::player::test_player(&args, 1);
+        for [x, y] in [[isize::MAX, 0], [0, isize::MIN], [-3, 5], [4, 40]] {
+            let before = player.board();
+            player.apply(Action::Place {rotation: 0, x, y});
+            assert_eq!(player.board(), before);
+            player.apply(Action::HardDrop);
+        }
+    }
+}
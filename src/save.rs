@@ -0,0 +1,144 @@
+use std::{fs, io, path::PathBuf, sync::{Mutex, atomic::Ordering}, time::Duration};
+use serde::{Serialize, Deserialize};
+
+use crate::{piece_set::PieceSet, player::{Action, Player}, VERSION};
+
+// A game quit halfway through, kept the same way as replays: pieces & garbage holes come from the seeds, so the events get it back to exactly where it was
+#[derive(Serialize, Deserialize)]
+pub struct Save {
+    version: String,
+    pub pieces_name: String, //what --pieces was, so that the game ends up on the same high score table
+    pub pieces: String,      //piece set as the file's contents
+    pub speed: f64,
+    pub lock_delay: u64,
+    pub strict_finesse: bool,
+    players: Vec<SavedPlayer>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedPlayer {
+    seed: u64,
+    events: Vec<(u64, Action)>,
+    time: u64, //milliseconds played
+    keys: usize,
+    incoming: usize, //garbage on its way that hasn't risen yet
+}
+
+fn path() -> Option<PathBuf> {dirs::data_dir().map(|dir| dir.join("tetris-rs").join("save.json"))}
+
+impl Save {
+    pub fn take(args: &crate::Args, piece_set: &PieceSet, players: &[Mutex<Player>]) -> Self {
+        Self {
+            version: String::from(VERSION),
+            pieces_name: args.pieces.clone(),
+            pieces: piece_set.source.clone(),
+            speed: args.speed,
+            lock_delay: args.lock_delay,
+            strict_finesse: args.strict_finesse,
+            players: players.iter().map(|player| {
+                let player = player.lock().unwrap();
+                SavedPlayer {seed: player.seed, events: player.events.clone(), time: player.time().as_millis() as u64, keys: player.keys, incoming: player.incoming.load(Ordering::SeqCst)}
+            }).collect(),
+        }
+    }
+
+    pub fn write(&self) -> io::Result<()> {
+        let path = path().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?;
+        if let Some(dir) = path.parent() {fs::create_dir_all(dir)?;}
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    pub fn load() -> Result<Self, String> {
+        let json = path().and_then(|path| fs::read_to_string(path).ok()).ok_or("no saved game to resume")?;
+        let save: Self = serde_json::from_str(&json).map_err(|e| format!("invalid saved game: {}", e))?;
+        if save.version != VERSION {return Err(format!("game was saved with version {}, this is {}", save.version, VERSION))}
+        if !(1..=2).contains(&save.players.len()) {return Err(String::from("saved game doesn't have one or two players"))}
+        log::info!("resuming a game saved with {} pieces", save.pieces_name);
+        Ok(save)
+    }
+
+    // Once resumed, the same game can't be continued twice
+    pub fn remove() {
+        if let Some(path) = path() {_=fs::remove_file(path);}
+    }
+
+    pub fn seeds(&self) -> Vec<u64> {self.players.iter().map(|player| player.seed).collect()}
+
+    // Play everything that happened again on freshly created players, before they get to attack each other
+    pub fn restore(&self, players: &mut [Player]) {
+        for (player, saved) in players.iter_mut().zip(&self.players) {
+            for &(at, action) in &saved.events { //at the time it happened, finesse tells taps from held keys by it
+                player.set_time(Duration::from_millis(at));
+                player.apply(action);
+            }
+            player.finish_clear();
+            player.events = saved.events.clone();
+            player.keys = saved.keys;
+            player.incoming.store(saved.incoming, Ordering::SeqCst); //only once the events are played, so none of it rises while they are
+            player.resume(Duration::from_millis(saved.time));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    // Whatever happened before saving, the resumed game is the same one
+    #[test]
+    fn round_trip() {
+        let args = crate::Args::parse_from(["tetris-rs", "--split-screen"]);
+        let piece_set = PieceSet::load("classic").unwrap();
+        let new = |seed| crate::player::test_player(&args, seed);
+
+        let players = [Mutex::new(new(1)), Mutex::new(new(2))];
+        for (i, player) in players.iter().enumerate() {
+            let player = &mut player.lock().unwrap();
+            for piece in 0..60 {
+                if piece % 9 == 0 {player.act(Action::Garbage {lines: 1 + i});}
+                if crate::ai::play(player, &crate::ai::DEFAULT_WEIGHTS) {player.act(Action::Restart);}
+            }
+            player.incoming.store(3 + i, Ordering::SeqCst); //sent, but not risen yet
+        }
+        let save: Save = serde_json::from_str(&serde_json::to_string(&Save::take(&args, &piece_set, &players)).unwrap()).unwrap();
+
+        let mut resumed = [new(0), new(0)];
+        for (player, seed) in resumed.iter_mut().zip(save.seeds()) {*player = new(seed);}
+        save.restore(&mut resumed);
+        for (player, resumed) in players.iter().zip(&resumed) {
+            let player = &mut player.lock().unwrap();
+            player.finish_clear(); //resumed games don't wait for the animation either
+            assert_eq!((player.board(), player.score, player.lines, player.drawn), (resumed.board(), resumed.score, resumed.lines, resumed.drawn));
+            assert_eq!(player.current_block.piece.name, resumed.current_block.piece.name);
+            assert_eq!(player.incoming.load(Ordering::SeqCst), resumed.incoming.load(Ordering::SeqCst));
+            assert!(resumed.time() >= Duration::from_millis(save.players[0].time.min(save.players[1].time)));
+        }
+    }
+
+    // Strict finesse decides by when keys were pressed, resuming has to see the same taps & holds
+    #[test]
+    fn strict_finesse() {
+        let args = crate::Args::parse_from(["tetris-rs", "--strict-finesse"]);
+        let piece_set = PieceSet::load("classic").unwrap();
+        let new = |seed| crate::player::test_player(&args, seed);
+
+        let players = [Mutex::new(new(7))];
+        {
+            let player = &mut players[0].lock().unwrap();
+            let taps = [0, 150, 300, 450, 600].map(|ms| (ms, Action::Left)); //a fault, sent back up
+            let held = [1000, 1500, 1533, 1566].map(|ms| (ms, Action::Left)); //straight to the wall
+            for (ms, action) in taps.into_iter().chain([(700, Action::HardDrop)]).chain(held).chain([(1600, Action::HardDrop)]) {
+                player.set_time(Duration::from_millis(ms));
+                player.act(action);
+            }
+            assert_eq!((player.placed, player.finesse.as_ref().unwrap().faults), (1, 1));
+        }
+        let save = Save::take(&args, &piece_set, &players);
+
+        let mut resumed = [new(7)];
+        save.restore(&mut resumed);
+        let (player, resumed) = (players[0].lock().unwrap(), &resumed[0]);
+        assert_eq!((player.board(), player.placed, player.finesse.as_ref().unwrap().faults), (resumed.board(), resumed.placed, resumed.finesse.as_ref().unwrap().faults));
+    }
+}
@@ -0,0 +1,51 @@
+use std::{io, sync::{Arc, atomic::{AtomicBool, Ordering}}, time::{Duration, Instant}};
+
+// What the game loop has to deal with
+pub enum Signal {
+    Quit,    //SIGTERM & SIGHUP, same as pressing Esc
+    Suspend, //SIGTSTP, Ctrl+Z ends up here as well
+    Resumed, //SIGCONT without a SIGTSTP first, someone else stopped the process & might've messed with the terminal
+}
+
+// Signal handlers can't do much safely, so they only set flags for the game loop to check on every pass
+#[derive(Default)]
+pub struct Signals {
+    quit: Arc<AtomicBool>,
+    suspend: Arc<AtomicBool>,
+    resumed: Arc<AtomicBool>,
+}
+impl Signals {
+    #[cfg(unix)]
+    pub fn register() -> io::Result<Self> {
+        use signal_hook::{consts::{SIGCONT, SIGHUP, SIGTERM, SIGTSTP}, flag};
+        let signals = Self::default();
+        for signal in [SIGTERM, SIGHUP] {flag::register(signal, Arc::clone(&signals.quit))?;}
+        flag::register(SIGTSTP, Arc::clone(&signals.suspend))?;
+        flag::register(SIGCONT, Arc::clone(&signals.resumed))?;
+        Ok(signals)
+    }
+    #[cfg(not(unix))]
+    pub fn register() -> io::Result<Self> {Ok(Self::default())}
+
+    pub fn take(&self) -> Option<Signal> {
+        if self.quit.swap(false, Ordering::SeqCst) {Some(Signal::Quit)}
+        else if self.suspend.swap(false, Ordering::SeqCst) {Some(Signal::Suspend)}
+        else if self.resumed.swap(false, Ordering::SeqCst) {Some(Signal::Resumed)}
+        else {None}
+    }
+
+    // Stop the whole process until it gets continued, returns for how long it was stopped
+    pub fn stop(&self) -> Duration {
+        let start = Instant::now();
+        #[cfg(unix)]
+        {_=signal_hook::low_level::raise(signal_hook::consts::SIGSTOP);} //SIGTSTP is handled, so it wouldn't stop anything
+        self.resumed.store(false, Ordering::SeqCst); //that SIGCONT was this one's, it's been dealt with
+        start.elapsed()
+    }
+}
+
+// What the terminal would've done with Ctrl+Z if it wasn't in raw mode
+pub fn ctrl_z() {
+    #[cfg(unix)]
+    {_=signal_hook::low_level::raise(signal_hook::consts::SIGTSTP);}
+}
@@ -0,0 +1,166 @@
+use std::{thread, sync::{Arc, Mutex}, io::{self, BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, time::Duration};
+use bombs::Fuse;
+use crossterm::style::Color;
+use serde::{Serialize, Deserialize};
+
+use crate::{renderable_object::*, rendering::Redraw, player::Player, PF_HEIGHT, PF_WIDTH, VERSION};
+
+// Protocol, one JSON object per line, only ever sent from the server to viewers
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    Hello {version: String, palette: Vec<Color>},
+    State {players: Vec<PlayerState>},
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PlayerState {
+    board: Vec<Vec<u8>>, //playfield with the active piece drawn onto it
+    held: Vec<Vec<u8>>,
+    queue: Vec<String>, //names of the upcoming pieces
+    panel: Vec<String>, //score, level, lines, time, combo & back-to-back, as on the HUD
+    stats: Vec<String>, //the --stats panel, if the game has one
+}
+
+fn line(msg: &Message) -> io::Result<Vec<u8>> {
+    let mut buf = serde_json::to_vec(msg)?;
+    buf.push(b'\n');
+    Ok(buf)
+}
+
+// Server, broadcasts the game state to every connected viewer once per frame
+pub fn bind(addr: &str) -> io::Result<TcpListener> {
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+pub fn server_thread(
+    args: Arc<crate::Args>,
+    bomb: bombs::Bomb<()>,
+    listener: TcpListener,
+    players: Arc<Vec<Mutex<Player>>>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let frame = Duration::from_nanos(1_000_000_000/args.framerate as u64);
+        let palette = players.first().map_or(Vec::new(), |player| player.lock().unwrap().playfield.palette.to_vec());
+        let mut viewers: Vec<TcpStream> = Vec::new();
+        let mut last_state = Vec::new();
+
+    // Main loop
+        while bomb.exploded().is_none() { //check for close signal
+        // Accept new viewers
+            while let Ok((mut stream, addr)) = listener.accept() {
+                log::info!("spectator connected from {}", addr);
+                let hello = line(&Message::Hello {version: String::from(VERSION), palette: palette.clone()}).unwrap();
+                if stream.set_nonblocking(false).and(stream.set_nodelay(true)).and(stream.set_write_timeout(Some(frame))).and(stream.write_all(&hello)).is_ok() {
+                    if !last_state.is_empty() {_=stream.write_all(&last_state);} //don't wait for the next change
+                    viewers.push(stream);
+                }
+            }
+
+        // Send the game state if it changed, dropping viewers that can't keep up
+            let state = line(&Message::State {players: players.iter().map(|player| player.lock().unwrap().spectate()).collect()}).unwrap();
+            if state != last_state {
+                viewers.retain_mut(|viewer| viewer.write_all(&state).inspect_err(|e| log::info!("dropped spectator {:?}: {}", viewer.peer_addr(), e)).is_ok());
+                last_state = state;
+            }
+
+            thread::sleep(frame);
+        }
+    })
+}
+
+impl Player {
+    fn spectate(&mut self) -> PlayerState {
+        self.update_hud();
+        self.update_stats();
+        let text = |obj: &RenderableObject| obj.shape.iter().map(|row| String::from_utf8_lossy(row).into_owned()).collect();
+        PlayerState {
+            board: self.board(),
+            held: self.held(),
+            queue: self.queue.iter().map(|block| block.piece.name.clone()).collect(),
+            panel: text(&self.panel),
+            stats: self.stats.as_ref().map_or(Vec::new(), text),
+        }
+    }
+}
+
+// Viewer, renders the received game state read-only
+pub fn watch(addr: &str) -> io::Result<(BufReader<TcpStream>, Arc<[Color]>)> {
+    let mut reader = BufReader::new(TcpStream::connect(addr)?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    match serde_json::from_str(&line) {
+        Ok(Message::Hello {version, palette}) if version == VERSION => Ok((reader, palette.into())),
+        Ok(Message::Hello {..}) => Err(io::Error::new(io::ErrorKind::InvalidData, "server is running a different version")),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "server isn't a tetris-rs spectator server")),
+    }
+}
+
+pub fn viewer_thread(
+    args: Arc<crate::Args>,
+    bomb: bombs::Bomb<()>,
+    mut reader: BufReader<TcpStream>,
+    palette: Arc<[Color]>,
+    objects: Arc<Mutex<Vec<RenderableObject>>>,
+    redraw: Arc<Redraw>,
+    fuse: Arc<Mutex<Option<Fuse<()>>>>
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let scale = (args.width_scale as isize, args.vertical_scale as isize);
+        _=reader.get_ref().set_read_timeout(Some(Duration::from_nanos(1_000_000_000/args.framerate as u64)));
+
+    // Main loop
+        let mut line = String::new();
+        while bomb.exploded().is_none() { //check for close signal
+            match reader.read_line(&mut line) {
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => (),
+                Ok(0) | Err(_) => {
+                    log::warn!("spectator stream ended");
+                    let banner = objects.lock().unwrap().first().map(|playfield| crate::rendering::banner(playfield, b"STREAM ENDED", b"Esc to exit."));
+                    objects.lock().unwrap().extend(banner);
+                    crate::rendering::game_over(&redraw, &fuse, &[]);
+                    break;
+                }
+                Ok(_) => {
+                    if let Ok(Message::State {players}) = serde_json::from_str(&line) {
+                        *objects.lock().unwrap() = layout(players, &palette, scale, args.half_blocks);
+                        redraw.request();
+                    }
+                    line.clear();
+                }
+            }
+        }
+    })
+}
+
+// Playfields, held blocks, upcoming pieces & panels next to each other, same as in a local game
+fn layout(players: Vec<PlayerState>, palette: &Arc<[Color]>, scale: (isize, isize), half: bool) -> Vec<RenderableObject> {
+    let sanitize = |rows: Vec<Vec<u8>>| rows.into_iter().map(|row| row.into_iter().map(|col| if col < 0x20 {col} else {1}).collect()).collect();
+    let text = |pos: [isize; 2], lines: Vec<String>, width: usize, rows: usize| RenderableObject::new(pos, lines.into_iter().take(rows)
+        .map(|line| line.bytes().filter(|b| (b' '..=b'~').contains(b)).take(width).collect()).collect(), (1,1), false); //printable only, & no bigger than locally
+
+    let mut objects = Vec::with_capacity(players.len()*5);
+    let mut offset = 3;
+    for player in players {
+        if player.board.len() != PF_HEIGHT as usize || player.board.iter().any(|row| row.len() != PF_WIDTH) || player.held.is_empty() {continue}
+        let (hold_width, hold_rows) = (player.held[0].len() as isize, player.held.len() as isize);
+
+        let mut playfield = RenderableObject::new([offset, 0], sanitize(player.board), scale, true);
+        let mut held = RenderableObject::new([offset + PF_WIDTH as isize*scale.0 + (PF_WIDTH as isize-hold_width)/2*scale.0+1, 0], sanitize(player.held), scale, true);
+        playfield.palette = Arc::clone(palette);
+        held.palette = Arc::clone(palette);
+        (playfield.half, held.half) = (half, half);
+
+        let [x, held_lines] = [held.pos[0], held.lines(hold_rows)];
+        let queue = text([x, held_lines+3], vec![String::from("NEXT"), player.queue.join(" ")], crate::hud::WIDTH, 2);
+        let panel = text([x, held_lines+6], player.panel, crate::hud::WIDTH, PF_HEIGHT as usize);
+        let stats = text([x + crate::hud::WIDTH as isize + 2, held_lines+3], player.stats, 4*crate::hud::WIDTH, PF_HEIGHT as usize);
+
+        let right = |obj: &RenderableObject| obj.pos[0] + obj.shape.iter().map(Vec::len).max().unwrap_or(0) as isize;
+        offset = (x + hold_width*scale.0 + 2).max(right(&panel)).max(right(&stats)) + 2;
+        objects.extend([playfield, held, queue, panel, stats]);
+    }
+    objects
+}
@@ -0,0 +1,46 @@
+use std::collections::VecDeque;
+
+use crate::{renderable_object::*, player::Player};
+
+const WIDTH: usize = 10; //of every line & histogram column
+const LINES: usize = 6;  //before the histogram
+const BAR: usize = 4;    //longest histogram bar
+
+// Empty panel, the histogram wraps into more columns instead of going past the playfield's bottom
+pub fn panel(pieces: usize, pos: [isize; 2], playfield_lines: isize) -> RenderableObject {
+    let rows = ((playfield_lines + 2 - pos[1]) as usize).saturating_sub(LINES).max(1);
+    let columns = pieces.div_ceil(rows);
+    RenderableObject::new(pos, VecDeque::from(vec![vec![b' '; columns*(WIDTH+1)-1]; LINES + pieces.min(rows)]), (1,1), false)
+}
+
+impl Player {
+    pub fn update_stats(&mut self) {
+        let Some(stats) = &self.stats else {return};
+        let (secs, placed) = (self.time().as_secs_f64().max(1.), self.placed.max(1) as f64); //no infinities in the first second
+        let rows = stats.shape.len() - LINES;
+        let width = stats.shape[0].len();
+
+        let mut lines = vec![
+            format!("PIECES{:>4}", self.placed),
+            format!("PPS{:>7.2}", self.placed as f64/secs),
+            format!("APM{:>7.1}", self.attack as f64*60./secs),
+            format!("KPP{:>7.2}", self.keys as f64/placed),
+            format!("LINES{:>5}", self.lines),
+            format!("TIME{:>3}:{:02}", secs as u64/60, secs as u64%60),
+        ];
+        lines.resize(stats.shape.len(), String::new());
+
+        let most = self.histogram.iter().copied().max().unwrap_or(0).max(1);
+        for (i, (count, block)) in self.histogram.iter().zip(self.defs.iter()).enumerate() {
+            let bar = "#".repeat((count*BAR).div_ceil(most));
+            let line = &mut lines[LINES + i%rows];
+            if i >= rows {line.push(' ');}
+            line.push_str(&format!("{:<2.2}{:>3} {:<BAR$}", block.piece.name, count, bar));
+        }
+
+        let stats = self.stats.as_mut().unwrap();
+        for (row, line) in stats.shape.iter_mut().zip(lines) {
+            *row = format!("{:<width$.width$}", line).into_bytes();
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::{io, panic, sync::atomic::{AtomicBool, Ordering}};
+use crossterm::{execute, cursor, event::{DisableMouseCapture, EnableMouseCapture}, terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen}};
+
+static TAKEN_OVER: AtomicBool = AtomicBool::new(false); //whether the terminal still has to be put back
+
+// Raw mode & everything else the game changes about the terminal, put back once this is dropped or anything panics
+pub struct Guard {
+    fullscreen: bool,
+}
+impl Guard {
+    pub fn new(fullscreen: bool) -> io::Result<Self> {
+        let guard = Self {fullscreen}; //undoes whatever worked if anything fails
+        guard.resume()?;
+
+    // Panics in other threads never get to the guard, the game can't go on without them anyway
+        let default = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            restore(fullscreen); //before the message, so that it shows up properly
+            log::error!("{}", info);
+            default(info);
+            std::process::exit(101);
+        }));
+        Ok(guard)
+    }
+
+    // Hand the terminal back for a while, e.g. while the process is suspended
+    pub fn suspend(&self) {restore(self.fullscreen)}
+
+    // Take the terminal over again, everything on it has to be drawn again afterwards
+    pub fn resume(&self) -> io::Result<()> {
+        terminal::enable_raw_mode()?; //handle *all* input manually, including stuff like ctrl+c
+        TAKEN_OVER.store(true, Ordering::SeqCst);
+        execute!(io::stdout(), EnableMouseCapture, cursor::Hide)?;
+        if self.fullscreen {execute!(io::stdout(), EnterAlternateScreen)?;}
+        Ok(())
+    }
+}
+impl Drop for Guard {
+    fn drop(&mut self) {restore(self.fullscreen)}
+}
+
+// Nothing to do if it has been put back already
+fn restore(fullscreen: bool) {
+    if !TAKEN_OVER.swap(false, Ordering::SeqCst) {return}
+    _=execute!(io::stdout(), Clear(ClearType::FromCursorDown)); //the cursor is back where the game started, unless it's panicking
+    if fullscreen {_=execute!(io::stdout(), LeaveAlternateScreen);}
+
+    _=terminal::disable_raw_mode();
+    _=execute!(io::stdout(), DisableMouseCapture, cursor::Show);
+}
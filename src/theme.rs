@@ -0,0 +1,173 @@
+use std::fs;
+use crossterm::style::Color;
+use serde::Deserialize;
+
+pub const BUILTIN: [(&str, &str); 4] = [
+    ("default",   include_str!("../themes/default.toml")),
+    ("mono",      include_str!("../themes/mono.toml")),
+    ("solarized", include_str!("../themes/solarized.toml")),
+    ("gameboy",   include_str!("../themes/gameboy.toml")),
+];
+
+// The 16 standard colours in ANSI order & roughly what they look like in xterm
+const ANSI16: [(Color, [u8; 3]); 16] = [
+    (Color::Black, [0, 0, 0]),         (Color::DarkRed, [205, 0, 0]),       (Color::DarkGreen, [0, 205, 0]),  (Color::DarkYellow, [205, 205, 0]),
+    (Color::DarkBlue, [0, 0, 238]),    (Color::DarkMagenta, [205, 0, 205]), (Color::DarkCyan, [0, 205, 205]), (Color::Grey, [229, 229, 229]),
+    (Color::DarkGrey, [127, 127, 127]), (Color::Red, [255, 0, 0]),          (Color::Green, [0, 255, 0]),      (Color::Yellow, [255, 255, 0]),
+    (Color::Blue, [92, 92, 255]),      (Color::Magenta, [255, 0, 255]),     (Color::Cyan, [0, 255, 255]),     (Color::White, [255, 255, 255]),
+];
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255]; //levels of the 6x6x6 colour cube in the 256 colour palette
+
+// File format, everything is optional
+#[derive(Deserialize)]
+struct ThemeDef {
+    #[serde(default)]
+    pieces: Vec<Color>,
+    garbage: Option<Color>,
+    ghost: Option<Color>,
+    border: Option<Color>,
+    text: Option<Color>,
+    highlight: Option<Color>,
+    background: Option<Color>,
+    cell: Option<char>,
+    ghost_cell: Option<char>,
+}
+
+// How many colours the terminal can show, colours get degraded to fit
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum ColourDepth {
+    Truecolor,
+    #[value(name = "256")]
+    Ansi256,
+    #[value(name = "16")]
+    Ansi16,
+}
+impl ColourDepth {
+    pub fn detect() -> Self {
+        if cfg!(windows) {return Self::Truecolor}
+        let (colorterm, term) = (std::env::var("COLORTERM").unwrap_or_default(), std::env::var("TERM").unwrap_or_default());
+        if colorterm == "truecolor" || colorterm == "24bit" {Self::Truecolor}
+        else if term.contains("256") {Self::Ansi256}
+        else {Self::Ansi16}
+    }
+
+    pub fn degrade(self, colour: Color) -> Color {
+        match (self, colour) {
+            (Self::Ansi256, Color::Rgb {r, g, b}) => Color::AnsiValue(to_256([r, g, b])),
+            (Self::Ansi16, Color::Rgb {r, g, b}) => to_16([r, g, b]),
+            (Self::Ansi16, Color::AnsiValue(n)) => to_16(from_256(n)),
+            _ => colour,
+        }
+    }
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {a.iter().zip(b).map(|(&a, b)| (a as i32 - b as i32).pow(2) as u32).sum()}
+
+fn to_16(rgb: [u8; 3]) -> Color {ANSI16.iter().min_by_key(|(_, ansi)| distance(rgb, *ansi)).map_or(Color::Reset, |&(colour, _)| colour)}
+
+// Closest of the colour cube & the greyscale ramp
+fn to_256(rgb: [u8; 3]) -> u8 {
+    let level = |c: u8| (0..6).min_by_key(|&i| (CUBE[i] as i32 - c as i32).abs()).unwrap_or(0);
+    let [r, g, b] = rgb.map(level);
+    let cube = 16 + 36*r as u8 + 6*g as u8 + b as u8;
+
+    let grey = ((rgb.iter().map(|&c| c as u32).sum::<u32>()/3).saturating_sub(3)/10).min(23) as u8;
+    if distance(rgb, from_256(232 + grey)) < distance(rgb, from_256(cube)) {232 + grey} else {cube}
+}
+
+fn from_256(n: u8) -> [u8; 3] {
+    match n {
+        0..=15 => ANSI16[n as usize].1,
+        16..=231 => [(n-16)/36, (n-16)/6%6, (n-16)%6].map(|i| CUBE[i as usize]),
+        _ => [8 + (n-232)*10; 3],
+    }
+}
+
+// Loaded themes, colours still have to go through colour() before being drawn
+#[derive(Debug)]
+pub struct Theme {
+    pieces: Vec<Color>, //replaces the piece set's colours, in order
+    pub garbage: Color,
+    pub ghost: Color,
+    pub border: Color,
+    pub text: Color,
+    pub highlight: Color, //new high scores
+    pub background: Option<Color>, //behind playfields & other boxes
+    pub cell: char,
+    pub ghost_cell: char,
+    depth: ColourDepth,
+}
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            pieces: Vec::new(), garbage: Color::Reset, ghost: Color::DarkGrey, border: Color::Reset, text: Color::Reset, highlight: Color::Yellow,
+            background: None, cell: '█', ghost_cell: '█', depth: ColourDepth::Truecolor,
+        }
+    }
+}
+impl Theme {
+    pub fn load(name_or_path: &str, depth: ColourDepth) -> Result<Self, String> {
+        match BUILTIN.iter().find(|(name, _)| *name == name_or_path) {
+            Some((_, src)) => Self::parse(src, depth),
+            None => Self::parse(&fs::read_to_string(name_or_path).map_err(|e| format!("couldn't read theme '{}': {}", name_or_path, e))?, depth),
+        }
+    }
+
+    pub fn parse(src: &str, depth: ColourDepth) -> Result<Self, String> {
+        let def: ThemeDef = toml::from_str(src).map_err(|e| format!("invalid theme: {}", e))?;
+        let default = Self::default();
+        Ok(Self {
+            pieces: def.pieces,
+            garbage: def.garbage.unwrap_or(default.garbage),
+            ghost: def.ghost.unwrap_or(default.ghost),
+            border: def.border.unwrap_or(default.border),
+            text: def.text.unwrap_or(default.text),
+            highlight: def.highlight.unwrap_or(default.highlight),
+            background: def.background,
+            cell: def.cell.unwrap_or(default.cell),
+            ghost_cell: def.ghost_cell.unwrap_or(default.ghost_cell),
+            depth,
+        })
+    }
+
+    // Colour of a piece, the theme's colours repeat if there are more pieces than colours
+    pub fn piece(&self, id: usize, palette: &[Color]) -> Color {
+        if self.pieces.is_empty() {palette.get(id).copied().unwrap_or(Color::Reset)} else {self.pieces[id % self.pieces.len()]}
+    }
+
+    pub fn colour(&self, colour: Color) -> Color {self.depth.degrade(colour)}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn rgb(r: u8, g: u8, b: u8) -> Color {Color::Rgb {r, g, b}}
+
+    #[test]
+    fn degrade() {
+        let cases = [
+            (ColourDepth::Truecolor, rgb(181, 137, 0), rgb(181, 137, 0)),
+            (ColourDepth::Ansi256, rgb(255, 0, 0), Color::AnsiValue(196)),
+            (ColourDepth::Ansi256, rgb(0, 0, 0), Color::AnsiValue(16)),
+            (ColourDepth::Ansi256, rgb(95, 135, 175), Color::AnsiValue(67)),
+            (ColourDepth::Ansi256, rgb(128, 128, 128), Color::AnsiValue(244)), //greyscale ramp, the cube has no grey that close
+            (ColourDepth::Ansi256, rgb(240, 240, 240), Color::AnsiValue(255)),
+            (ColourDepth::Ansi256, Color::AnsiValue(33), Color::AnsiValue(33)),
+            (ColourDepth::Ansi16, rgb(250, 10, 10), Color::Red),
+            (ColourDepth::Ansi16, rgb(0, 0, 200), Color::DarkBlue),
+            (ColourDepth::Ansi16, rgb(130, 130, 130), Color::DarkGrey),
+            (ColourDepth::Ansi16, rgb(181, 137, 0), Color::DarkYellow),
+            (ColourDepth::Ansi16, Color::AnsiValue(196), Color::Red),
+            (ColourDepth::Ansi16, Color::AnsiValue(5), Color::DarkMagenta),
+            (ColourDepth::Ansi16, Color::Cyan, Color::Cyan),
+        ];
+        for (depth, colour, expected) in cases {assert_eq!(depth.degrade(colour), expected, "{:?} at {:?}", colour, depth);}
+    }
+
+    // Every colour in the palette comes back as itself, from the cube & the greyscale ramp alike
+    #[test]
+    fn palette_round_trip() {
+        for n in 16..=255 {assert_eq!(to_256(from_256(n)), n);}
+    }
+}
@@ -0,0 +1,129 @@
+use std::{thread, sync::{Arc, Mutex, atomic::Ordering}, io::{self, BufRead, BufReader, Write}, net::{TcpListener, TcpStream}, time::Duration, collections::VecDeque};
+use bombs::Fuse;
+use serde::{Serialize, Deserialize};
+
+use crate::{renderable_object::*, piece_set::PieceSet, player::Player, PF_HEIGHT, PF_WIDTH, VERSION};
+
+// Protocol, one JSON object per line
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Message {
+    Hello {version: String, pieces: Vec<String>},
+    Board {rows: Vec<Vec<u8>>},
+    Garbage {lines: usize},
+    GameOver,
+}
+
+pub struct Link {
+    stream: TcpStream,
+    reader: Option<BufReader<TcpStream>>, //taken by the versus thread
+}
+impl Link {
+    pub fn host(addr: &str, args: &crate::Args, piece_set: &PieceSet) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        println!("Waiting for an opponent to connect to {}...", listener.local_addr()?);
+        Self::handshake(listener.accept()?.0, args, piece_set)
+    }
+    pub fn connect(addr: &str, args: &crate::Args, piece_set: &PieceSet) -> io::Result<Self> {
+        Self::handshake(TcpStream::connect(addr)?, args, piece_set)
+    }
+
+    fn handshake(stream: TcpStream, args: &crate::Args, piece_set: &PieceSet) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let pieces: Vec<String> = piece_set.pieces.iter().map(|p| p.name.clone()).collect();
+
+        let mut link = Self {stream, reader: None};
+        link.send(&Message::Hello {version: String::from(VERSION), pieces: pieces.clone()})?;
+
+        let mut reader = BufReader::new(link.stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        match serde_json::from_str(&line) {
+            Ok(Message::Hello {version, pieces: their_pieces}) if version == VERSION && their_pieces == pieces => (),
+            Ok(Message::Hello {..}) => return Err(io::Error::new(io::ErrorKind::InvalidData, "opponent is using a different version or piece set")),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "opponent isn't speaking the tetris-rs protocol")),
+        }
+
+        reader.get_ref().set_read_timeout(Some(Duration::from_nanos(1_000_000_000/args.framerate as u64)))?;
+        link.reader = Some(reader);
+        log::info!("playing versus against {}", link.stream.peer_addr()?);
+        Ok(link)
+    }
+
+    fn send(&mut self, msg: &Message) -> io::Result<()> {
+        let mut buf = serde_json::to_vec(msg)?;
+        buf.push(b'\n');
+        self.stream.write_all(&buf)
+    }
+
+    pub fn send_garbage(&mut self, lines: usize) -> io::Result<()> {self.send(&Message::Garbage {lines})}
+}
+
+pub fn thread(
+    bomb: bombs::Bomb<()>,
+    link: Arc<Mutex<Link>>,
+    objects: Arc<Mutex<Vec<RenderableObject>>>,
+    opponent_idx: usize, //index of the opponent's playfield in objects
+    players: Arc<Vec<Mutex<Player>>>,
+    fuse: Arc<Mutex<Option<Fuse<()>>>>
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+    // Main loop
+        let mut reader = link.lock().unwrap().reader.take().unwrap();
+        let (incoming, redraw) = {
+            let player = players[0].lock().unwrap();
+            (Arc::clone(&player.incoming), Arc::clone(&player.redraw))
+        };
+        let mut line = String::new();
+        let mut last_board = Vec::new();
+        let result = loop {
+            if bomb.exploded().is_some() {break None} //lost or quit
+
+        // Receive messages, times out after a frame
+            match reader.read_line(&mut line) {
+                Ok(0) => {log::warn!("opponent disconnected"); break Some(b" DISCONNECT ")}
+                Ok(_) => {
+                    match serde_json::from_str(&line) {
+                        Ok(Message::Board {rows}) if rows.len() == PF_HEIGHT as usize && rows.iter().all(|row| row.len() == PF_WIDTH) => {
+                            objects.lock().unwrap()[opponent_idx].shape = rows.into_iter()
+                                .map(|row| row.into_iter().map(|col| if col < 0x20 {col} else {1}).collect())
+                                .collect();
+                            redraw.request();
+                        }
+                        Ok(Message::Garbage {lines}) => {
+                            log::debug!("opponent sent {} garbage lines", lines);
+                            incoming.fetch_add(lines, Ordering::SeqCst);
+                            redraw.request();
+                        }
+                        Ok(Message::GameOver) => break Some(b" YOU   WIN! "),
+                        _ => (),
+                    }
+                    line.clear();
+                }
+                Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => (),
+                Err(e) => {log::warn!("lost the connection to the opponent: {}", e); break Some(b" DISCONNECT ")}
+            }
+
+        // Send own playfield if it changed
+            let board = players[0].lock().unwrap().board();
+            if board != last_board {
+                _=link.lock().unwrap().send(&Message::Board {rows: board.clone()});
+                last_board = board;
+            }
+        };
+
+    // Exit
+        match result {
+            Some(title) => crate::rendering::game_over(&redraw, &fuse, &[title]),
+            None => _=link.lock().unwrap().send(&Message::GameOver),
+        }
+    })
+}
+
+// Opponent's playfield, placed to the right of the player's
+pub fn opponent(player: &Player) -> RenderableObject {
+    let mut playfield = RenderableObject::new([player.right_edge()+2, 0], VecDeque::from(vec![vec![0u8; PF_WIDTH]; PF_HEIGHT as usize]), player.playfield.scale, true);
+    playfield.palette = Arc::clone(&player.playfield.palette);
+    playfield.half = player.playfield.half;
+    playfield
+}
@@ -0,0 +1,148 @@
+// Just enough of a terminal to replay what the renderer writes & look at the result, used by the snapshot tests
+// Anything the renderer sends that isn't understood here panics, so new escape sequences can't slip by untested
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cell {
+    ch: char,
+    fg: String, //SGR parameters the colour was set with, empty for the default colour
+    bg: String,
+}
+impl Cell {
+    fn blank(bg: &str) -> Self {Self {ch: ' ', fg: String::new(), bg: bg.to_string()}}
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Terminal {
+    width: usize,
+    height: usize,
+    cells: Vec<Vec<Cell>>,
+    cursor: (usize, usize),
+    fg: String,
+    bg: String,
+}
+impl Terminal {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {width, height, cells: vec![vec![Cell::blank(""); width]; height], cursor: (0, 0), fg: String::new(), bg: String::new()}
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let text = std::str::from_utf8(bytes).expect("renderer wrote invalid UTF-8");
+        let mut chars = text.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\x1b' => {
+                    assert_eq!(chars.next(), Some('['), "only CSI sequences are supported");
+                    let mut params = String::new();
+                    let command = loop {
+                        match chars.next() {
+                            Some(c @ ('0'..='9' | ';')) => params.push(c),
+                            Some(c) => break c,
+                            None => panic!("unterminated escape sequence"),
+                        }
+                    };
+                    self.csi(&params, command);
+                }
+                '\n' => { //raw mode, so only down
+                    if self.cursor.1 + 1 == self.height {
+                        self.cells.remove(0);
+                        self.cells.push(vec![Cell::blank(&self.bg); self.width]);
+                    } else {self.cursor.1 += 1;}
+                }
+                '\r' => self.cursor.0 = 0,
+                _ if ch.is_control() => panic!("unexpected control character {:?}", ch),
+                _ => {
+                    let (x, y) = self.cursor;
+                    if x < self.width {self.cells[y][x] = Cell {ch, fg: self.fg.clone(), bg: self.bg.clone()};}
+                    self.cursor.0 = (x + 1).min(self.width - 1);
+                }
+            }
+        }
+    }
+
+    fn csi(&mut self, params: &str, command: char) {
+        let n = |default: usize| params.parse().unwrap_or(default);
+        let (x, y) = self.cursor;
+        match command {
+            'A' => self.cursor.1 = y.saturating_sub(n(1)),
+            'B' => self.cursor.1 = (y + n(1)).min(self.height - 1),
+            'C' => self.cursor.0 = (x + n(1)).min(self.width - 1),
+            'D' => self.cursor.0 = x.saturating_sub(n(1)),
+            'G' => self.cursor.0 = (n(1).max(1) - 1).min(self.width - 1),
+            'H' => {
+                let mut pos = params.split(';').map(|p| p.parse::<usize>().unwrap_or(1).max(1) - 1);
+                let (row, col) = (pos.next().unwrap_or(0), pos.next().unwrap_or(0));
+                self.cursor = (col.min(self.width - 1), row.min(self.height - 1));
+            }
+            'J' => {
+                let from = match n(0) {
+                    0 => y*self.width + x,
+                    2 => 0,
+                    other => panic!("unsupported erase mode {}", other),
+                };
+                for i in from..self.width*self.height {self.cells[i/self.width][i%self.width] = Cell::blank(&self.bg);}
+            }
+            'm' => self.sgr(params),
+            _ => panic!("unsupported escape sequence ESC[{}{}", params, command),
+        }
+    }
+
+    fn sgr(&mut self, params: &str) {
+        let params: Vec<&str> = params.split(';').collect();
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                "" | "0" => {
+                    self.fg.clear();
+                    self.bg.clear();
+                }
+                "39" => self.fg.clear(),
+                "49" => self.bg.clear(),
+                p @ ("38" | "48") => {
+                    let len = if params.get(i+1) == Some(&"5") {2} else {4};
+                    let colour = params[i+1..=i+len].join(";");
+                    if p == "38" {self.fg = colour} else {self.bg = colour}
+                    i += len;
+                }
+                p => match p.parse::<u8>() {
+                    Ok(30..=37 | 90..=97) => self.fg = p.to_string(),
+                    Ok(40..=47 | 100..=107) => self.bg = p.to_string(),
+                    _ => panic!("unsupported SGR parameter {}", p),
+                },
+            }
+            i += 1;
+        }
+    }
+
+    // The screen as text, followed by a map of where which colours are & what they are
+    pub fn snapshot(&self) -> String {
+        let mut colours: Vec<(&str, &str)> = Vec::new();
+        let mut text = String::new();
+        let mut map = String::new();
+        for row in &self.cells {
+            let mut line = String::new();
+            let mut map_line = String::new();
+            for cell in row {
+                line.push(cell.ch);
+                let colour = (if cell.ch == ' ' {""} else {cell.fg.as_str()}, cell.bg.as_str()); //spaces have no foreground
+                map_line.push(match colour {
+                    ("", "") => '.',
+                    _ => {
+                        let i = colours.iter().position(|&c| c == colour).unwrap_or_else(|| {colours.push(colour); colours.len()-1});
+                        (b'a' + i as u8) as char
+                    }
+                });
+            }
+            text += line.trim_end();
+            text.push('\n');
+            map += map_line.trim_end_matches('.');
+            map.push('\n');
+        }
+
+        let trim = |s: &str| s.trim_end_matches('\n').to_string() + "\n";
+        let mut snapshot = trim(&text) + "\n" + &trim(&map) + "\n";
+        for (i, (fg, bg)) in colours.iter().enumerate() {
+            snapshot += &format!("{}: fg {} bg {}\n", (b'a' + i as u8) as char, if fg.is_empty() {"default"} else {fg}, if bg.is_empty() {"default"} else {bg});
+        }
+        snapshot
+    }
+}